@@ -0,0 +1,462 @@
+use std::{
+    collections::{BTreeMap, BinaryHeap},
+    f32::consts::PI,
+};
+
+use image::{imageops, DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+use imageproc::{
+    contrast,
+    distance_transform::euclidean_squared_distance_transform,
+    drawing, filter,
+    region_labelling::{connected_components, Connectivity},
+};
+use serde::Serialize;
+
+use crate::{
+    artifacts::{Artifact, ArtifactError, ArtifactSink, Table},
+    configuration::{BacteriaDetection, Polarity},
+};
+
+/// The number of line segments the outline of a bacterium is drawn with
+const OUTLINE_SEGMENTS: usize = 36;
+
+/// The fraction of the largest distance to the background within a region that its cores are
+/// deeper than, when splitting it into touching bacteria
+const CORE_DEPTH: f64 = 0.7;
+
+/// The labels of the regions of an image, where the background is 0
+type Labels = ImageBuffer<Luma<u32>, Vec<u32>>;
+
+/// A bacterium found in the image, described by the ellipse with the same second moments
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Bacterium {
+    /// The center of the bacterium in pixels
+    pub center: (f32, f32),
+    /// The length of the bacterium in micrometers
+    pub length: f32,
+    /// The width of the bacterium in micrometers
+    pub width: f32,
+    /// The angle of the long axis in radians, zero is horizontal and PI/2 is vertical
+    pub angle: f32,
+    /// The area covered by the bacterium in square micrometers
+    pub area: f32,
+}
+
+/// The bacteria found in an image
+#[derive(Debug, Clone, Serialize)]
+pub struct Detection {
+    pub bacteria: Vec<Bacterium>,
+    /// The number of bacteria per square millimeter
+    pub density: f32,
+    /// The ratio of the image covered by bacteria
+    pub coverage: f32,
+    /// The ratio of the image covered by regions that weren't accepted as bacteria, such as
+    /// debris or clumps of bacteria that couldn't be split
+    pub rejected_coverage: f32,
+}
+
+impl Detection {
+    /// The number of bacteria found
+    pub fn count(&self) -> usize {
+        self.bacteria.len()
+    }
+}
+
+/// The sums of the coordinates of the pixels in a region, for its moments
+#[derive(Debug, Clone, Copy, Default)]
+struct Moments {
+    count: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    xy: f64,
+    yy: f64,
+}
+
+impl Moments {
+    fn add(&mut self, x: u32, y: u32) {
+        let (x, y) = (f64::from(x), f64::from(y));
+
+        self.count += 1.0;
+        self.x += x;
+        self.y += y;
+        self.xx += x * x;
+        self.xy += x * y;
+        self.yy += y * y;
+    }
+
+    /// The ellipse with the same second moments. Returns (center, length, width, angle) with the
+    /// lengths in pixels
+    fn ellipse(&self) -> ((f32, f32), f32, f32, f32) {
+        let (cx, cy) = (self.x / self.count, self.y / self.count);
+
+        // The central second moments, including the spread of the pixels themselves
+        let xx = self.xx / self.count - cx * cx + 1.0 / 12.0;
+        let xy = self.xy / self.count - cx * cy;
+        let yy = self.yy / self.count - cy * cy + 1.0 / 12.0;
+
+        let mean = f64::midpoint(xx, yy);
+        let difference = ((xx - yy) / 2.0).hypot(xy);
+
+        // The variance along an axis of a filled ellipse is a quarter of the squared semi-axis
+        let length = 4.0 * (mean + difference).max(0.0).sqrt();
+        let width = 4.0 * (mean - difference).max(0.0).sqrt();
+        let angle = 0.5 * (2.0 * xy).atan2(xx - yy);
+
+        (
+            (cx as f32, cy as f32),
+            length as f32,
+            width as f32,
+            angle as f32,
+        )
+    }
+}
+
+/// Finds the bacteria as the regions brighter (or darker) than the threshold with a length and
+/// width within the configured ranges. Regions larger than a bacterium are split where they
+/// narrow first, as touching bacteria merge into one region
+pub fn bacteria_detection(
+    input_image: &GrayImage,
+    config: &BacteriaDetection,
+    scale: f32,
+    artifacts: &dyn ArtifactSink,
+) -> Result<Detection, ArtifactError> {
+    // Blur and threshold the image to separate the bacteria from the surface
+    let mut mask = if 0.0 < config.blur {
+        filter::gaussian_blur_f32(input_image, config.blur)
+    } else {
+        input_image.clone()
+    };
+    if config.polarity == Polarity::Dark {
+        imageops::invert(&mut mask);
+    }
+    contrast::threshold_mut(&mut mask, config.threshold);
+
+    // Describe every region by its moments, the background is label 0
+    let mut labels = connected_components(&mask, Connectivity::Eight, Luma([0]));
+    let mut regions = moments(&labels);
+
+    // Split the regions too large for a bacterium into the parts around their cores
+    let oversized: Vec<u32> = (1..regions.len())
+        .filter(|label| {
+            let (_, length, width, _) = regions[*label].ellipse();
+            0.0 < regions[*label].count
+                && (config.max_length < length * scale || config.max_width < width * scale)
+        })
+        .map(|label| label as u32)
+        .collect();
+    if !oversized.is_empty() {
+        let mut pixels: BTreeMap<u32, Vec<(u32, u32)>> =
+            oversized.iter().map(|label| (*label, Vec::new())).collect();
+        for (x, y, label) in labels.enumerate_pixels() {
+            if let Some(pixels) = pixels.get_mut(&label.0[0]) {
+                pixels.push((x, y));
+            }
+        }
+
+        // The distance of every pixel of a region to the background
+        let background = GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+            Luma([if mask.get_pixel(x, y).0[0] == 0 {
+                255
+            } else {
+                0
+            }])
+        });
+        let distances = euclidean_squared_distance_transform(&background);
+
+        let mut next_label = regions.len() as u32;
+        for pixels in pixels.values() {
+            split(&mut labels, pixels, &distances, &mut next_label);
+        }
+        regions = moments(&labels);
+    }
+
+    // Keep the regions with the size of a bacterium
+    let mut accepted = vec![false; regions.len()];
+    let mut bacteria = Vec::new();
+    let mut rejected = 0.0;
+    for (label, region) in regions.iter().enumerate().skip(1) {
+        if region.count == 0.0 {
+            continue;
+        }
+
+        let (center, length, width, angle) = region.ellipse();
+        let (length, width) = (length * scale, width * scale);
+        if !(config.min_length..=config.max_length).contains(&length)
+            || !(config.min_width..=config.max_width).contains(&width)
+        {
+            rejected += region.count;
+            continue;
+        }
+
+        accepted[label] = true;
+        bacteria.push(Bacterium {
+            center,
+            length,
+            width,
+            angle,
+            area: region.count as f32 * scale * scale,
+        });
+    }
+
+    let covered = labels
+        .pixels()
+        .filter(|label| accepted[label.0[0] as usize])
+        .count();
+    let pixels = (input_image.width() * input_image.height()).max(1) as f32;
+
+    // The area of the image in square millimeters
+    let area = pixels * scale * scale / 1e6;
+
+    if artifacts.accepts("bacteria_overlay.png") {
+        artifacts.emit(
+            "bacteria_overlay.png",
+            Artifact::Image(overlay(input_image, &labels, &accepted, &bacteria, scale).into()),
+        )?;
+    }
+
+    if artifacts.accepts("bacteria.csv") {
+        let table = Table {
+            header: ["x", "y", "length", "width", "angle", "area"]
+                .map(String::from)
+                .to_vec(),
+            rows: bacteria
+                .iter()
+                .map(|bacterium| {
+                    vec![
+                        format!("{:.1}", bacterium.center.0),
+                        format!("{:.1}", bacterium.center.1),
+                        format!("{:.3}", bacterium.length),
+                        format!("{:.3}", bacterium.width),
+                        format!("{:.3}", bacterium.angle.to_degrees()),
+                        format!("{:.3}", bacterium.area),
+                    ]
+                })
+                .collect(),
+        };
+
+        artifacts.emit("bacteria.csv", Artifact::Table(table))?;
+    }
+
+    Ok(Detection {
+        density: bacteria.len() as f32 / area,
+        coverage: covered as f32 / pixels,
+        rejected_coverage: rejected as f32 / pixels,
+        bacteria,
+    })
+}
+
+/// The moments of every region by its label
+fn moments(labels: &Labels) -> Vec<Moments> {
+    let mut regions = vec![
+        Moments::default();
+        labels.pixels().map(|label| label.0[0]).max().unwrap_or(0) as usize + 1
+    ];
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label.0[0] != 0 {
+            regions[label.0[0] as usize].add(x, y);
+        }
+    }
+
+    regions
+}
+
+/// Splits a region by a watershed of the distance to the background. The cores of the region,
+/// its connected parts deeper than [`CORE_DEPTH`] of the deepest pixel, are grown back over the
+/// region deepest pixels first, so touching bacteria are split at the neck between them. Every
+/// part but the first gets a new label starting at `next_label`
+fn split(
+    labels: &mut Labels,
+    pixels: &[(u32, u32)],
+    distances: &ImageBuffer<Luma<f64>, Vec<f64>>,
+    next_label: &mut u32,
+) {
+    let Some(&(x, y)) = pixels.first() else {
+        return;
+    };
+    let label = labels.get_pixel(x, y).0[0];
+    let (width, height) = labels.dimensions();
+
+    // The squared distances are whole numbers
+    let distance = |(x, y): (u32, u32)| distances.get_pixel(x, y).0[0] as u64;
+    let deepest = pixels
+        .iter()
+        .map(|pixel| distance(*pixel))
+        .max()
+        .unwrap_or(0);
+    let is_core = |pixel| CORE_DEPTH * CORE_DEPTH * deepest as f64 <= distance(pixel) as f64;
+
+    let neighbours = |(x, y): (u32, u32)| {
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|offset| *offset != (0, 0))
+            .filter_map(move |(dx, dy)| {
+                Some((x.checked_add_signed(dx)?, y.checked_add_signed(dy)?))
+            })
+            .filter(move |(x, y)| *x < width && *y < height)
+    };
+
+    // Find the cores and the part of the region every pixel belongs to
+    let mut parts: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+    let mut flood = BinaryHeap::new();
+    let mut cores = 0;
+    for &pixel in pixels {
+        if !is_core(pixel) || parts.contains_key(&pixel) {
+            continue;
+        }
+
+        parts.insert(pixel, cores);
+        let mut stack = vec![pixel];
+        while let Some(pixel) = stack.pop() {
+            flood.push((distance(pixel), pixel));
+            for neighbour in neighbours(pixel) {
+                if labels.get_pixel(neighbour.0, neighbour.1).0[0] == label
+                    && is_core(neighbour)
+                    && !parts.contains_key(&neighbour)
+                {
+                    parts.insert(neighbour, cores);
+                    stack.push(neighbour);
+                }
+            }
+        }
+        cores += 1;
+    }
+
+    if cores < 2 {
+        return;
+    }
+
+    while let Some((_, pixel)) = flood.pop() {
+        let part = parts[&pixel];
+        for neighbour in neighbours(pixel) {
+            if labels.get_pixel(neighbour.0, neighbour.1).0[0] == label
+                && !parts.contains_key(&neighbour)
+            {
+                parts.insert(neighbour, part);
+                flood.push((distance(neighbour), neighbour));
+            }
+        }
+    }
+
+    for ((x, y), part) in parts {
+        if 0 < part {
+            labels.put_pixel(x, y, Luma([*next_label + part - 1]));
+        }
+    }
+    *next_label += cores - 1;
+}
+
+/// The image with the accepted regions tinted and the ellipse and long axis of every bacterium
+/// drawn on top
+fn overlay(
+    input_image: &GrayImage,
+    labels: &Labels,
+    accepted: &[bool],
+    bacteria: &[Bacterium],
+    scale: f32,
+) -> RgbImage {
+    let mut image = DynamicImage::ImageLuma8(input_image.clone()).into_rgb8();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if accepted[labels.get_pixel(x, y).0[0] as usize] {
+            pixel.0[1] = pixel.0[1] / 2 + 128;
+        }
+    }
+
+    for bacterium in bacteria {
+        let (cx, cy) = bacterium.center;
+        let (a, b) = (
+            bacterium.length / scale / 2.0,
+            bacterium.width / scale / 2.0,
+        );
+        let (sin, cos) = bacterium.angle.sin_cos();
+        let point = |t: f32| {
+            let (x, y) = (a * t.cos(), b * t.sin());
+            (cx + x * cos - y * sin, cy + x * sin + y * cos)
+        };
+
+        for i in 0..OUTLINE_SEGMENTS {
+            let step = 2.0 * PI / OUTLINE_SEGMENTS as f32;
+            drawing::draw_line_segment_mut(
+                &mut image,
+                point(i as f32 * step),
+                point((i + 1) as f32 * step),
+                Rgb([255, 0, 255]),
+            );
+        }
+
+        drawing::draw_line_segment_mut(&mut image, point(0.0), point(PI), Rgb([255, 0, 0]));
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_4, PI};
+
+    use image::{GrayImage, Luma};
+
+    use super::bacteria_detection;
+    use crate::{
+        artifacts::NoSink,
+        configuration::{BacteriaDetection, Polarity},
+    };
+
+    /// The size of a pixel (um / px)
+    const SCALE: f32 = 0.1;
+
+    /// Draws a filled ellipse with the given semi-axes (px) and angle of the long axis
+    fn draw_ellipse(image: &mut GrayImage, center: (f32, f32), a: f32, b: f32, angle: f32) {
+        let (sin, cos) = angle.sin_cos();
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (dx, dy) = (x as f32 - center.0, y as f32 - center.1);
+            let (along, across) = (dx * cos + dy * sin, dy * cos - dx * sin);
+            if (along / a).powi(2) + (across / b).powi(2) <= 1.0 {
+                *pixel = Luma([255]);
+            }
+        }
+    }
+
+    #[test]
+    fn measures_and_splits_ellipses() {
+        // Bacteria 3 by 1 um: two lying apart, two touching end to end and a speck of debris
+        let mut image = GrayImage::new(200, 160);
+        draw_ellipse(&mut image, (40.0, 40.0), 15.0, 5.0, 0.0);
+        draw_ellipse(&mut image, (130.0, 50.0), 15.0, 5.0, FRAC_PI_4);
+        draw_ellipse(&mut image, (40.0, 120.0), 15.0, 5.0, 0.0);
+        draw_ellipse(&mut image, (69.0, 120.0), 15.0, 5.0, 0.0);
+        for (x, y) in [(150, 130), (151, 130), (150, 131), (151, 131)] {
+            image.put_pixel(x, y, Luma([255]));
+        }
+
+        let config = BacteriaDetection {
+            enabled: true,
+            blur: 0.0,
+            threshold: 128,
+            polarity: Polarity::Light,
+            min_length: 1.0,
+            max_length: 4.0,
+            min_width: 0.5,
+            max_width: 1.5,
+        };
+        let detection = bacteria_detection(&image, &config, SCALE, &NoSink).unwrap();
+
+        assert_eq!(detection.count(), 4);
+        for bacterium in &detection.bacteria {
+            assert!((bacterium.length - 3.0).abs() < 0.15, "{bacterium:?}");
+            assert!((bacterium.width - 1.0).abs() < 0.1, "{bacterium:?}");
+
+            let expected = if 100.0 < bacterium.center.0 {
+                FRAC_PI_4
+            } else {
+                0.0
+            };
+            assert!((bacterium.angle - expected).abs() < 0.05, "{bacterium:?}");
+        }
+
+        let pixels = (image.width() * image.height()) as f32;
+        let coverage = 4.0 * PI * 15.0 * 5.0 / pixels;
+        assert!((detection.coverage - coverage).abs() < 0.05 * coverage);
+        assert!((detection.rejected_coverage - 4.0 / pixels).abs() < 1e-6);
+    }
+}
@@ -1,40 +1,124 @@
 use std::f32::consts::PI;
 
-use image::{DynamicImage, GrayImage, ImageBuffer, Rgb, RgbImage};
+use image::{imageops, DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
 use imageproc::{
     contours::find_contours_with_threshold,
     distance_transform::euclidean_squared_distance_transform, drawing, geometry::convex_hull,
     point::Point,
 };
+use serde::Serialize;
 
 use crate::{
-    algorithms::helpers::{absolute_contrast_threshold, filter_by_minimum_area},
-    algorithms::Error,
-    configuration::BacteriaExclusion,
+    algorithms::bacterium_placement::blocked_fraction,
+    algorithms::edge_detection::detect_edges,
+    algorithms::edge_orientation::{edge_weights, WEIGHT_LEVELS},
+    algorithms::helpers::{filter_by_minimum_area, filter_by_minimum_size},
+    algorithms::thresholding::select_threshold,
+    algorithms::{Error, Flake},
+    artifacts::{Artifact, ArtifactError, ArtifactSink, Table},
+    configuration::{BacteriaExclusion, EdgeDetector, ExclusionMode, ThresholdSelection},
 };
 
+/// The area within range of a graphene edge, where bacteria are excluded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Exclusion {
+    /// The ratio of the entire image within range of an edge
+    pub ratio: f32,
+    /// The ratio of the full circle estimated from the radial sample, if enabled
+    pub radius_adjusted_ratio: Option<f32>,
+    /// The threshold on the edge strength used for the image by the contrast or gradient
+    /// detector, whether fixed, selected or Otsu's level of the gradient
+    pub contrast_threshold: Option<f32>,
+    /// The fraction of the positions and orientations where a bacterium can be placed without
+    /// touching an edge, for the bacterium exclusion mode
+    pub placeable_ratio: Option<f32>,
+}
+
+/// The graphene edges found in an image
+#[derive(Debug, Clone)]
+pub struct Edges {
+    /// The edges with the noise filtered out
+    pub mask: GrayImage,
+    /// The threshold on the edge strength used for the image by the contrast or gradient
+    /// detector, whether fixed, selected or Otsu's level of the gradient
+    pub contrast_threshold: Option<f32>,
+}
+
+impl Exclusion {
+    /// The radius adjusted ratio if available, otherwise the ratio of the entire image
+    pub fn value(&self) -> f32 {
+        self.radius_adjusted_ratio.unwrap_or(self.ratio)
+    }
+}
+
+/// Calculates the area within range of a graphene edge. The flakes are only used by the
+/// directional exclusion mode with the orientation of the flakes
 pub fn bacteria_exclusion(
     input_image: &GrayImage,
     config: &BacteriaExclusion,
+    flakes: &[Flake],
     scale: f32,
-    debug: bool,
-    output_prefix: &str,
-) -> Result<f32, Box<dyn std::error::Error>> {
-    // Find sharp contrasts in each direction individually and then absolutely combine
-    // them to find the edges. This differs from doing it combined with a single kernel
-    // in that it favors contrast in only one direction to better find graphene flakes.
-    let (edges, edge_sharpness) =
-        absolute_contrast_threshold(input_image, config.contrast_threshold);
-
-    if debug {
-        // Save the edge sharpness
-        edge_sharpness.save(output_prefix.to_string() + "edge_sharpness.png")?;
+    artifacts: &dyn ArtifactSink,
+) -> Result<Exclusion, Box<dyn std::error::Error + Send + Sync>> {
+    let edges = graphene_edges(input_image, config, artifacts)?;
+
+    exclusion_zone(input_image, &edges, flakes, config, scale, artifacts)
+}
+
+/// Finds the graphene edges in the image as a mask, with the noise filtered out
+pub fn graphene_edges(
+    input_image: &GrayImage,
+    config: &BacteriaExclusion,
+    artifacts: &dyn ArtifactSink,
+) -> Result<Edges, ArtifactError> {
+    // Find the edges with the configured detector, by default sharp contrasts in each direction
+    // individually that are then absolutely combined
+    let (mut edges, edge_sharpness, mut contrast_threshold) =
+        detect_edges(input_image, config.edge_detector, config.contrast_threshold);
+
+    // Choose the threshold on the strength of the contrast and gradient detectors for this
+    // image unless it is fixed, where the detectors keep the configured threshold and Otsu's
+    // method respectively. Canny's detector and the Laplacian of Gaussian have their own
+    // thresholds
+    if config.selects_threshold() && config.threshold_selection != ThresholdSelection::Fixed {
+        let (selected_edges, threshold) = select_threshold(
+            &edge_sharpness,
+            config.threshold_selection,
+            config.contrast_threshold,
+            artifacts,
+        )?;
+
+        edges = selected_edges;
+        contrast_threshold = Some(threshold);
     }
 
-    // Filter the edges by area to remove noise
-    let filtered_edges = filter_by_minimum_area(&edges, config.minimum_edge_area);
+    // Save the edge sharpness and the edges before they are filtered
+    if artifacts.accepts("edge_sharpness.png") {
+        artifacts.emit(
+            "edge_sharpness.png",
+            Artifact::Image(DynamicImage::ImageLuma8(edge_sharpness)),
+        )?;
+    }
+
+    if artifacts.accepts("edge_map.png") {
+        artifacts.emit(
+            "edge_map.png",
+            Artifact::Image(DynamicImage::ImageLuma8(edges.clone())),
+        )?;
+    }
 
-    if debug {
+    // Filter the edges by size to remove noise, the thin edges of Canny and the Laplacian of
+    // Gaussian have no area so their pixels are counted instead
+    let filtered_edges = match config.edge_detector {
+        EdgeDetector::Contrast | EdgeDetector::Gradient { .. } => {
+            filter_by_minimum_area(&edges, config.minimum_edge_area)
+        }
+        EdgeDetector::Canny { .. } | EdgeDetector::LaplacianOfGaussian { .. } => {
+            filter_by_minimum_size(&edges, config.minimum_edge_area)
+        }
+    };
+
+    if artifacts.accepts("graphene.png") {
         // Visualize the edges overlayed with the original image
         let mut color_image: RgbImage = DynamicImage::ImageLuma8(input_image.clone()).into_rgb8();
         for (x, y, pixel) in color_image.enumerate_pixels_mut() {
@@ -43,41 +127,149 @@ pub fn bacteria_exclusion(
             }
         }
 
-        color_image.save(output_prefix.to_string() + "graphene.png")?;
+        artifacts.emit("graphene.png", Artifact::Image(color_image.into()))?;
     }
 
+    Ok(Edges {
+        mask: filtered_edges,
+        contrast_threshold,
+    })
+}
+
+/// Calculates the area within range of the edges found by [`graphene_edges`]
+pub fn exclusion_zone(
+    input_image: &GrayImage,
+    edges: &Edges,
+    flakes: &[Flake],
+    config: &BacteriaExclusion,
+    scale: f32,
+    artifacts: &dyn ArtifactSink,
+) -> Result<Exclusion, Box<dyn std::error::Error + Send + Sync>> {
     // Create a bacteria exclusion zone around all edges by thresholding the distance to the
     // closests detected edge
     let bacteria_exclusion_radius = config.exclusion_radius / scale;
-    if bacteria_exclusion_radius < 1.0 {
+    let smallest_size = match config.exclusion_mode {
+        ExclusionMode::Bacterium { width, .. } => width / scale,
+        _ => bacteria_exclusion_radius,
+    };
+    if smallest_size < 1.0 {
         return Err(Box::new(Error::ToSmallExclusionDiameter));
     }
 
-    let distances = euclidean_squared_distance_transform(&filtered_edges);
     let mut bacteria_exclusion_zone: GrayImage =
         ImageBuffer::new(input_image.width(), input_image.height());
 
-    // Count the percentage of white in the bacteria whitemask
-    let mut non_zero_count = 0;
-    for (x, y, pixel) in bacteria_exclusion_zone.enumerate_pixels_mut() {
-        if distances.get_pixel(x, y).0[0] < bacteria_exclusion_radius.into() {
-            pixel.0[0] = 255;
-            non_zero_count += 1;
+    let mut placeable_ratio = None;
+    match config.exclusion_mode {
+        ExclusionMode::Isotropic => {
+            exclude_within(
+                &mut bacteria_exclusion_zone,
+                &edges.mask,
+                bacteria_exclusion_radius,
+            );
+        }
+        ExclusionMode::Directional {
+            orientation,
+            surface_normal,
+            tolerance,
+            weighting,
+        } => {
+            let weights = edge_weights(
+                input_image,
+                &edges.mask,
+                flakes,
+                orientation,
+                surface_normal,
+                tolerance,
+                weighting,
+                scale,
+            );
+
+            // Every weight gets its own radius, so the edges of each weight are excluded around
+            // separately and combined
+            for level in 1..=WEIGHT_LEVELS {
+                let mut mask = GrayImage::new(weights.width(), weights.height());
+                let mut any = false;
+                for (x, y, weight) in weights.enumerate_pixels() {
+                    if weight.0[0] == level {
+                        mask.put_pixel(x, y, Luma([255]));
+                        any = true;
+                    }
+                }
+
+                if any {
+                    let radius =
+                        bacteria_exclusion_radius * f32::from(level) / f32::from(WEIGHT_LEVELS);
+                    exclude_within(&mut bacteria_exclusion_zone, &mask, radius);
+                }
+            }
+
+            if artifacts.accepts("edge_weights.png") {
+                let scaled = GrayImage::from_fn(weights.width(), weights.height(), |x, y| {
+                    Luma([weights.get_pixel(x, y).0[0] * (u8::MAX / WEIGHT_LEVELS)])
+                });
+
+                artifacts.emit(
+                    "edge_weights.png",
+                    Artifact::Image(DynamicImage::ImageLuma8(scaled)),
+                )?;
+            }
+        }
+        ExclusionMode::Bacterium {
+            shape,
+            length,
+            width,
+            orientations,
+        } => {
+            // The zone is the fraction of the orientations where the bacterium can't be placed
+            let (blocked, ratio) = blocked_fraction(
+                &edges.mask,
+                shape,
+                length / scale,
+                width / scale,
+                orientations,
+            );
+            bacteria_exclusion_zone = blocked;
+            placeable_ratio = Some(1.0 - ratio);
+
+            if artifacts.accepts("placeable_area.png") {
+                let mut placeable = bacteria_exclusion_zone.clone();
+                imageops::invert(&mut placeable);
+
+                artifacts.emit(
+                    "placeable_area.png",
+                    Artifact::Image(DynamicImage::ImageLuma8(placeable)),
+                )?;
+            }
         }
     }
 
-    // Calculate a percentage of whiteness
-    let mut bacteria_exclusion_ratio = non_zero_count as f32
-        / (bacteria_exclusion_zone.width() * bacteria_exclusion_zone.height()) as f32;
+    // Calculate a percentage of whiteness, where gray pixels are partly excluded
+    let bacteria_exclusion_ratio = if let Some(placeable_ratio) = placeable_ratio {
+        1.0 - placeable_ratio
+    } else {
+        let whiteness: u64 = bacteria_exclusion_zone
+            .pixels()
+            .map(|pixel| u64::from(pixel.0[0]))
+            .sum();
+
+        whiteness as f32
+            / 255.0
+            / (bacteria_exclusion_zone.width() * bacteria_exclusion_zone.height()) as f32
+    };
 
     // Export images for insight into algorithm
-    if debug {
-        bacteria_exclusion_zone.save(output_prefix.to_string() + "bacteria-exclusion.png")?;
+    if artifacts.accepts("bacteria-exclusion.png") {
+        artifacts.emit(
+            "bacteria-exclusion.png",
+            Artifact::Image(DynamicImage::ImageLuma8(bacteria_exclusion_zone.clone())),
+        )?;
     }
 
     // calculate the bacteria exclusion adjusted from a radius sample. We assume the image is a
     // stiched version going from the edge to the center. The circle center is assumed to be
     // halfway down in the y axis and all the way to the right in x axis
+    let mut radius_adjusted_ratio = None;
     if config.radius_adjusted {
         // Identify the regions outside of the stitch
         let outer_contours = find_contours_with_threshold(input_image, 1);
@@ -92,7 +284,7 @@ pub fn bacteria_exclusion(
         );
 
         // Export the hull as a image
-        if debug {
+        if artifacts.accepts("radius_hull.png") {
             let mut color_image: RgbImage =
                 DynamicImage::ImageLuma8(input_image.clone()).into_rgb8();
             let mut previous_point = *hull.last().unwrap();
@@ -107,7 +299,7 @@ pub fn bacteria_exclusion(
                 previous_point = *point;
             }
 
-            color_image.save(output_prefix.to_string() + "radius_hull.png")?;
+            artifacts.emit("radius_hull.png", Artifact::Image(color_image.into()))?;
         }
 
         let mut radius_buckets = vec![(0.0, 0); input_image.width() as usize];
@@ -140,10 +332,8 @@ pub fn bacteria_exclusion(
                 continue;
             }
 
-            // If the pixel is white add one to the radius bucket, otherwise nothing
-            if pixel.0[0] == 255 {
-                radius_buckets[distance].0 += 1.0;
-            }
+            // Add how excluded the pixel is to the radius bucket, one for white pixels
+            radius_buckets[distance].0 += f32::from(pixel.0[0]) / 255.0;
 
             radius_buckets[distance].1 += 1;
         }
@@ -164,26 +354,67 @@ pub fn bacteria_exclusion(
         }
 
         // Calculate the exclusion ratio
-        bacteria_exclusion_ratio =
-            bacteria_exclusion / ((input_image.width() as f32 - 1.0).powi(2) * PI);
+        radius_adjusted_ratio =
+            Some(bacteria_exclusion / ((input_image.width() as f32 - 1.0).powi(2) * PI));
 
         // Export all the radius buckets as a CSV
-        let mut csv = csv::Writer::from_writer(
-            std::fs::File::create(output_prefix.to_string() + "graphene_by_radius.csv")
-                .expect("Failed to open CSV file"),
-        );
+        if artifacts.accepts("graphene_by_radius.csv") {
+            let table = Table {
+                header: vec!["radial_distance".to_string(), "ratio".to_string()],
+                rows: radius_buckets
+                    .iter()
+                    .enumerate()
+                    .map(|(distance, (value, _))| {
+                        vec![format!("{}", (distance as f32) * scale), format!("{value}")]
+                    })
+                    .collect(),
+            };
 
-        // Write header to file
-        csv.write_record(["radial_distance", "ratio"])?;
+            artifacts.emit("graphene_by_radius.csv", Artifact::Table(table))?;
+        }
+    }
 
-        for (distance, (value, _)) in radius_buckets.iter().enumerate() {
-            csv.write_record(&[
-                format!("{}", (distance as f32) * scale),
-                format!("{}", value),
-            ])
-            .expect("Failed to write angles");
+    Ok(Exclusion {
+        ratio: bacteria_exclusion_ratio,
+        radius_adjusted_ratio,
+        contrast_threshold: edges.contrast_threshold,
+        placeable_ratio,
+    })
+}
+
+/// Marks the pixels of the zone within the radius (px) of the edges
+fn exclude_within(zone: &mut GrayImage, edges: &GrayImage, radius: f32) {
+    // The distances are squared
+    let distances = euclidean_squared_distance_transform(edges);
+    let radius = f64::from(radius).powi(2);
+    for (x, y, pixel) in zone.enumerate_pixels_mut() {
+        if distances.get_pixel(x, y).0[0] < radius {
+            pixel.0[0] = 255;
         }
     }
+}
 
-    Ok(bacteria_exclusion_ratio)
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::exclude_within;
+
+    #[test]
+    fn zone_extends_the_radius_from_the_edge() {
+        // A vertical line in the middle of the image
+        let mut edges = GrayImage::new(61, 61);
+        for y in 0..61 {
+            edges.put_pixel(30, y, Luma([255]));
+        }
+
+        for (radius, expected) in [(1.5, 3), (5.0, 9), (12.5, 25)] {
+            let mut zone = GrayImage::new(61, 61);
+            exclude_within(&mut zone, &edges, radius);
+
+            // The pixels closer than the radius on both sides of the line
+            let width = (0..61).filter(|x| zone.get_pixel(*x, 30).0[0] != 0).count();
+            assert_eq!(width, expected, "radius {radius}");
+        }
+    }
 }
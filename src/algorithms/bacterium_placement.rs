@@ -0,0 +1,149 @@
+use std::f32::consts::PI;
+
+use image::{GrayImage, Luma};
+use imageproc::distance_transform::euclidean_squared_distance_transform;
+use rayon::prelude::*;
+
+use crate::configuration::BacteriumShape;
+
+/// Finds where a bacterium of the given shape touches an edge, by dilating the edges with the
+/// shape in every orientation. Returns (the fraction of the orientations that touch an edge at
+/// every pixel scaled to 0-255, the fraction of all the positions and orientations that do)
+///
+/// The dilation with a rotated shape is the union of the dilations with the disks inscribed in
+/// it along its axis, so every disk is found from the distance transform of the edges instead
+/// of stamping the whole shape around every edge pixel. The length and width are in pixels
+pub fn blocked_fraction(
+    edges: &GrayImage,
+    shape: BacteriumShape,
+    length: f32,
+    width: f32,
+    orientations: u32,
+) -> (GrayImage, f32) {
+    let orientations = orientations.max(1);
+    let distances = euclidean_squared_distance_transform(edges);
+
+    // The disks of every orientation as (x offset, y offset, squared radius)
+    let disks = axis_disks(shape, length, width);
+    let kernels: Vec<Vec<(i32, i32, f64)>> = (0..orientations)
+        .map(|i| {
+            let angle = PI * i as f32 / orientations as f32;
+            disks
+                .iter()
+                .map(|(along, radius)| {
+                    (
+                        (along * angle.cos()).round() as i32,
+                        (along * angle.sin()).round() as i32,
+                        f64::from(radius * radius),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    // Every orientation touches an edge closer than half the width, and none touches one
+    // further away than half the length
+    let inner = f64::from(length.min(width) / 2.0).powi(2);
+    let outer = f64::from(length.max(width) / 2.0).powi(2);
+
+    let (image_width, image_height) = (edges.width(), edges.height());
+    let counts: Vec<u32> = (0..image_width * image_height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % image_width, i / image_width);
+            let distance = distances.get_pixel(x, y).0[0];
+            if distance < inner {
+                return orientations;
+            }
+            if outer <= distance {
+                return 0;
+            }
+
+            kernels
+                .iter()
+                .filter(|kernel| {
+                    kernel.iter().any(|(dx, dy, radius)| {
+                        let (sx, sy) = (x as i32 + dx, y as i32 + dy);
+
+                        // Nothing is known about the edges outside of the image
+                        0 <= sx
+                            && 0 <= sy
+                            && sx < image_width as i32
+                            && sy < image_height as i32
+                            && distances.get_pixel(sx as u32, sy as u32).0[0] < *radius
+                    })
+                })
+                .count() as u32
+        })
+        .collect();
+
+    let blocked = GrayImage::from_fn(image_width, image_height, |x, y| {
+        let count = counts[(y * image_width + x) as usize];
+        Luma([(255.0 * count as f32 / orientations as f32).round() as u8])
+    });
+    let ratio = counts.iter().map(|count| u64::from(*count)).sum::<u64>() as f32
+        / (u64::from(orientations) * counts.len().max(1) as u64) as f32;
+
+    (blocked, ratio)
+}
+
+/// The disks making up the shape lying along the x axis, as (center on the axis, radius). They
+/// are the largest disks inside the shape centered on its medial axis, spaced a pixel apart
+fn axis_disks(shape: BacteriumShape, length: f32, width: f32) -> Vec<(f32, f32)> {
+    let a = length.max(width) / 2.0;
+    let b = length.min(width) / 2.0;
+
+    // The medial axis runs between the centers of the ends of the capsule, and between the
+    // centers of curvature at the ends of the major axis of the ellipse
+    let half_axis = match shape {
+        BacteriumShape::Capsule => a - b,
+        BacteriumShape::Ellipse => (a * a - b * b) / a,
+    };
+    let radius = |along: f32| match shape {
+        BacteriumShape::Capsule => b,
+        BacteriumShape::Ellipse => {
+            if a <= b {
+                b
+            } else {
+                b * (1.0 - along * along / (a * a - b * b)).max(0.0).sqrt()
+            }
+        }
+    };
+
+    let steps = (2.0 * half_axis).ceil().max(0.0) as u32;
+    if steps == 0 {
+        return vec![(0.0, radius(0.0))];
+    }
+
+    (0..=steps)
+        .map(|i| {
+            let along = -half_axis + 2.0 * half_axis * i as f32 / steps as f32;
+            (along, radius(along))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::blocked_fraction;
+    use crate::configuration::BacteriumShape;
+
+    #[test]
+    fn rod_fits_along_a_line_but_not_across() {
+        // A vertical line and a rod 20 by 4 pixels
+        let mut edges = GrayImage::new(41, 41);
+        for y in 0..41 {
+            edges.put_pixel(20, y, Luma([255]));
+        }
+
+        let (blocked, _) = blocked_fraction(&edges, BacteriumShape::Capsule, 20.0, 4.0, 4);
+
+        // Right next to the line nothing fits, far from it everything does and in between only
+        // the vertical orientation (parallel to the line)
+        assert_eq!(blocked.get_pixel(21, 20).0[0], 255);
+        assert_eq!(blocked.get_pixel(35, 20).0[0], 0);
+        assert_eq!(blocked.get_pixel(25, 20).0[0], 191);
+    }
+}
@@ -0,0 +1,361 @@
+use image::{GrayImage, Luma};
+use imageproc::{
+    distance_transform::Norm,
+    morphology,
+    region_labelling::{connected_components, Connectivity},
+};
+
+use std::sync::LazyLock;
+
+/// The glyph templates, see the file for a description of the format
+static TEMPLATE_SOURCE: &str = include_str!("./glyphs.txt");
+
+/// The parsed glyph templates
+static TEMPLATES: LazyLock<Vec<Template>> = LazyLock::new(|| parse_templates(TEMPLATE_SOURCE));
+
+/// The number of cells the coverage of a glyph is sampled in
+const GRID_WIDTH: usize = 10;
+const GRID_HEIGHT: usize = 14;
+
+/// A rendered glyph to compare the characters in the image with
+struct Template {
+    character: char,
+    /// The top of the glyph above the baseline relative to the height of the digits
+    top: f32,
+    /// The bottom of the glyph below the baseline relative to the height of the digits
+    bottom: f32,
+    /// The width divided by the height of the glyph
+    aspect: f32,
+    /// The coverage of every cell in the grid from 0 to 1, row by row
+    coverage: Vec<f32>,
+}
+
+/// Text recognized in an image together with how well the characters matched the templates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recognition {
+    pub text: String,
+    /// How well the worst matching character matched its template, from 0 to 1
+    pub confidence: f32,
+}
+
+/// A connected group of pixels, hopefully a single character
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    label: u32,
+    min_x: u32,
+    max_x: u32,
+    min_y: u32,
+    max_y: u32,
+}
+
+impl Glyph {
+    fn width(&self) -> u32 {
+        self.max_x - self.min_x + 1
+    }
+
+    fn height(&self) -> u32 {
+        self.max_y - self.min_y + 1
+    }
+}
+
+/// Recognizes the text of a scale bar label in a black and white image with white text. Only the
+/// limited alphabet of SEM footers is recognized (digits, '.' and the units nm, µm, um and mm),
+/// by matching every character against templates of the glyphs in common footer fonts
+pub fn recognize_label(image: &GrayImage) -> Recognition {
+    // Find all the connected groups of white pixels. Thin strokes of anti-aliased text often
+    // break apart when thresholded, so gaps of a pixel or two are bridged first
+    let mut binary = image.clone();
+    for pixel in binary.pixels_mut() {
+        pixel.0[0] = if 127 < pixel.0[0] { 255 } else { 0 };
+    }
+    let bridged = morphology::dilate(&binary, Norm::LInf, 1);
+    let mut labels = connected_components(&bridged, Connectivity::Eight, Luma([0]));
+
+    // Only keep the labels of the original pixels, so the glyphs have their actual size
+    for (label, pixel) in labels.pixels_mut().zip(binary.pixels()) {
+        if pixel.0[0] == 0 {
+            label.0[0] = 0;
+        }
+    }
+
+    // Find the bounding box of every group
+    let mut glyphs: Vec<Option<Glyph>> = Vec::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label.0[0];
+        if label == 0 {
+            continue;
+        }
+
+        if glyphs.len() <= label as usize {
+            glyphs.resize(label as usize + 1, None);
+        }
+
+        let glyph = glyphs[label as usize].get_or_insert(Glyph {
+            label,
+            min_x: x,
+            max_x: x,
+            min_y: y,
+            max_y: y,
+        });
+        glyph.min_x = glyph.min_x.min(x);
+        glyph.max_x = glyph.max_x.max(x);
+        glyph.min_y = glyph.min_y.min(y);
+        glyph.max_y = glyph.max_y.max(y);
+    }
+
+    let mut glyphs: Vec<Glyph> = glyphs.into_iter().flatten().collect();
+    glyphs.sort_by_key(|glyph| glyph.min_x);
+
+    let Some(tallest) = glyphs.iter().map(Glyph::height).max() else {
+        return Recognition {
+            text: String::new(),
+            confidence: 0.0,
+        };
+    };
+
+    // The baseline is the most common bottom of the characters, ignoring dots and noise. The
+    // height of the digits is the highest any character reaches above it
+    let mut bottoms: Vec<u32> = glyphs
+        .iter()
+        .filter(|glyph| tallest < glyph.height() * 2)
+        .map(|glyph| glyph.max_y + 1)
+        .collect();
+    bottoms.sort_unstable();
+    let baseline = bottoms[bottoms.len() / 2];
+
+    let digit_height = glyphs
+        .iter()
+        .filter(|glyph| tallest < glyph.height() * 2)
+        .map(|glyph| baseline.saturating_sub(glyph.min_y))
+        .max()
+        .unwrap_or(tallest)
+        .max(1) as f32;
+
+    let mut text = String::new();
+    let mut confidence = 1.0_f32;
+    let mut previous_end = None;
+    for glyph in &glyphs {
+        let height = glyph.height() as f32 / digit_height;
+        let width = glyph.width() as f32 / digit_height;
+
+        // Small glyphs sitting on the baseline are decimal points, other small glyphs are noise
+        if height < 0.3 {
+            if width < 0.35 && (baseline as f32 - glyph.max_y as f32) < 0.2 * digit_height {
+                text.push('.');
+                previous_end = Some(glyph.max_x);
+            }
+
+            continue;
+        }
+
+        // Separate words by a space if there is a large enough gap between the characters
+        if let Some(previous_end) = previous_end {
+            if 0.6 * digit_height < glyph.min_x.saturating_sub(previous_end) as f32 {
+                text.push(' ');
+            }
+        }
+        previous_end = Some(glyph.max_x);
+
+        let (character, score) = classify(&labels, glyph, baseline, digit_height);
+        text.push(character);
+        confidence = confidence.min((1.0 - score).clamp(0.0, 1.0));
+    }
+
+    Recognition { text, confidence }
+}
+
+/// Finds the template most similar to the glyph and returns its character and the difference
+/// between them (0 is a perfect match)
+fn classify(
+    labels: &image::ImageBuffer<Luma<u32>, Vec<u32>>,
+    glyph: &Glyph,
+    baseline: u32,
+    digit_height: f32,
+) -> (char, f32) {
+    let top = (baseline as f32 - glyph.min_y as f32) / digit_height;
+    let bottom = (glyph.max_y as f32 + 1.0 - baseline as f32) / digit_height;
+    let aspect = glyph.width() as f32 / glyph.height() as f32;
+
+    // Sample the coverage of the glyph in the same grid as the templates
+    let (width, height) = (glyph.width() as usize, glyph.height() as usize);
+    let mut coverage = Vec::with_capacity(GRID_WIDTH * GRID_HEIGHT);
+    for grid_y in 0..GRID_HEIGHT {
+        for grid_x in 0..GRID_WIDTH {
+            let x_range = cell_range(grid_x, width, GRID_WIDTH);
+            let y_range = cell_range(grid_y, height, GRID_HEIGHT);
+
+            let mut covered = 0;
+            for y in y_range.clone() {
+                for x in x_range.clone() {
+                    let pixel = labels.get_pixel(glyph.min_x + x as u32, glyph.min_y + y as u32);
+                    if pixel.0[0] == glyph.label {
+                        covered += 1;
+                    }
+                }
+            }
+
+            coverage.push(covered as f32 / (x_range.len() * y_range.len()) as f32);
+        }
+    }
+
+    let mut best = (' ', f32::MAX);
+    for template in TEMPLATES.iter() {
+        let shape = chamfer_distance(&coverage, &template.coverage) / 4.0;
+
+        let score = shape
+            + 0.5 * (top - template.top).abs()
+            + 0.5 * (bottom - template.bottom).abs()
+            + 0.25 * (aspect / template.aspect).ln().abs();
+
+        if score < best.1 {
+            best = (template.character, score);
+        }
+    }
+
+    best
+}
+
+/// The average distance in cells from the covered cells of one grid to the closest covered cell
+/// of the other, in both directions. Unlike comparing the coverage directly this barely depends
+/// on the stroke width of the font or how much of it survived thresholding
+fn chamfer_distance(a: &[f32], b: &[f32]) -> f32 {
+    let covered = |grid: &[f32]| -> Vec<(f32, f32)> {
+        grid.iter()
+            .enumerate()
+            .filter(|(_, coverage)| 0.5 <= **coverage)
+            .map(|(i, _)| ((i % GRID_WIDTH) as f32, (i / GRID_WIDTH) as f32))
+            .collect()
+    };
+
+    let directed = |from: &[(f32, f32)], to: &[(f32, f32)]| -> f32 {
+        if from.is_empty() || to.is_empty() {
+            return GRID_HEIGHT as f32;
+        }
+
+        from.iter()
+            .map(|(x, y)| {
+                to.iter()
+                    .map(|(to_x, to_y)| (x - to_x).hypot(y - to_y))
+                    .fold(f32::MAX, f32::min)
+            })
+            .sum::<f32>()
+            / from.len() as f32
+    };
+
+    let (a, b) = (covered(a), covered(b));
+
+    f32::midpoint(directed(&a, &b), directed(&b, &a))
+}
+
+/// The range of pixels in a cell when dividing a length into a number of cells, every cell
+/// contains at least one pixel
+fn cell_range(cell: usize, length: usize, cells: usize) -> std::ops::Range<usize> {
+    let start = (cell * length / cells).min(length - 1);
+    let end = ((cell + 1) * length / cells).max(start + 1);
+
+    start..end
+}
+
+/// Parses the templates from the text format described in the template file
+fn parse_templates(source: &str) -> Vec<Template> {
+    let mut templates = Vec::new();
+    let mut lines = source
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty());
+
+    while let Some(header) = lines.next() {
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        let number = |i: usize| -> f32 { fields[i].parse().expect("Invalid glyph template") };
+
+        let coverage = lines
+            .by_ref()
+            .take(GRID_HEIGHT)
+            .flat_map(|row| row.chars())
+            .map(|cell| cell.to_digit(10).expect("Invalid glyph template") as f32 / 9.0)
+            .collect::<Vec<_>>();
+        assert_eq!(coverage.len(), GRID_WIDTH * GRID_HEIGHT);
+
+        templates.push(Template {
+            character: fields[0].chars().next().expect("Invalid glyph template"),
+            top: number(1),
+            bottom: number(2),
+            aspect: number(3),
+            coverage,
+        });
+    }
+
+    templates
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::{recognize_label, GRID_HEIGHT, GRID_WIDTH, TEMPLATES};
+
+    /// The height of the digits in the rendered labels (px)
+    const DIGIT_HEIGHT: f32 = 28.0;
+
+    /// Renders a label from the first template of every character, with '.' as a small square
+    /// on the baseline and ' ' as a gap
+    fn render(label: &str) -> GrayImage {
+        let baseline = 40;
+        let mut image = GrayImage::new(300, 60);
+        let mut x = 5;
+
+        for character in label.chars() {
+            match character {
+                ' ' => x += (0.8 * DIGIT_HEIGHT) as u32,
+                '.' => {
+                    for dy in 0..4 {
+                        for dx in 0..4 {
+                            image.put_pixel(x + dx, baseline - 1 - dy, Luma([255]));
+                        }
+                    }
+                    x += 8;
+                }
+                character => {
+                    let template = TEMPLATES
+                        .iter()
+                        .find(|template| template.character == character)
+                        .expect("There is no template for the character");
+
+                    let top = baseline - (template.top * DIGIT_HEIGHT).round() as u32;
+                    let height = ((template.top + template.bottom) * DIGIT_HEIGHT).round() as u32;
+                    let width = (template.aspect * height as f32).round() as u32;
+
+                    for y in 0..height {
+                        for dx in 0..width {
+                            let cell = (y as usize * GRID_HEIGHT / height as usize) * GRID_WIDTH
+                                + dx as usize * GRID_WIDTH / width as usize;
+                            if 0.5 <= template.coverage[cell] {
+                                image.put_pixel(x + dx, top + y, Luma([255]));
+                            }
+                        }
+                    }
+                    x += width + 4;
+                }
+            }
+        }
+
+        image
+    }
+
+    #[test]
+    fn recognizes_rendered_labels() {
+        for label in ["200 nm", "2.5 µm", "10 um", "1 mm", "0.5 µm", "3.721 nm"] {
+            let recognition = recognize_label(&render(label));
+
+            assert_eq!(recognition.text, label);
+            assert!(0.5 < recognition.confidence, "{label}: {recognition:?}");
+        }
+    }
+
+    #[test]
+    fn empty_image_has_no_confidence() {
+        let recognition = recognize_label(&GrayImage::new(50, 20));
+
+        assert_eq!(recognition.text, "");
+        assert!(recognition.confidence.abs() < f32::EPSILON);
+    }
+}
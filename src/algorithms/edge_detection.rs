@@ -0,0 +1,284 @@
+use image::{GrayImage, ImageBuffer, Luma};
+use imageproc::{
+    contrast::otsu_level,
+    edges::canny,
+    filter::{filter3x3, gaussian_blur_f32},
+    gradients::{horizontal_scharr, horizontal_sobel, vertical_scharr, vertical_sobel},
+};
+
+use crate::{
+    algorithms::helpers::absolute_contrast_threshold,
+    configuration::{EdgeDetector, GradientOperator},
+};
+
+/// An image of floating point values, such as a filter response
+type FloatImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// The standard deviation of the blur Canny's detector applies before the gradient
+const CANNY_SIGMA: f32 = 1.4;
+
+/// Finds the edges in the image with the given detector. Returns (edges, edge strength, level),
+/// where the strength is scaled to 0-255 for detectors without a natural range and the level is
+/// the threshold on the strength that separates the edges, for the contrast and gradient
+/// detectors
+pub fn detect_edges(
+    image: &GrayImage,
+    detector: EdgeDetector,
+    contrast_threshold: f32,
+) -> (GrayImage, GrayImage, Option<f32>) {
+    match detector {
+        EdgeDetector::Contrast => {
+            let (edges, contrast) = absolute_contrast_threshold(image, contrast_threshold);
+
+            (edges, contrast, Some(contrast_threshold))
+        }
+        EdgeDetector::Canny {
+            low_threshold,
+            high_threshold,
+        } => {
+            // Canny doesn't expose its gradient, so the strength is calculated the same way
+            let magnitude = gradient_magnitude(
+                &gaussian_blur_f32(image, CANNY_SIGMA),
+                GradientOperator::Sobel,
+            );
+
+            (
+                canny(
+                    image,
+                    low_threshold.min(high_threshold),
+                    high_threshold.max(low_threshold),
+                ),
+                magnitude,
+                None,
+            )
+        }
+        EdgeDetector::Gradient { operator, blur } => {
+            let magnitude = if 0.0 < blur {
+                gradient_magnitude(&gaussian_blur_f32(image, blur), operator)
+            } else {
+                gradient_magnitude(image, operator)
+            };
+
+            // Separate the edges from the background by the histogram of the magnitude
+            let level = otsu_level(&magnitude);
+            let mut edges = magnitude.clone();
+            for pixel in edges.pixels_mut() {
+                pixel.0[0] = if level < pixel.0[0] { 255 } else { 0 };
+            }
+
+            (edges, magnitude, Some(f32::from(level)))
+        }
+        EdgeDetector::LaplacianOfGaussian {
+            minimum_sigma,
+            maximum_sigma,
+            scales,
+            threshold,
+        } => {
+            let (edges, strength) =
+                laplacian_of_gaussian(image, minimum_sigma, maximum_sigma, scales, threshold);
+
+            (edges, strength, None)
+        }
+    }
+}
+
+/// The magnitude of the gradient scaled to 0-255
+fn gradient_magnitude(image: &GrayImage, operator: GradientOperator) -> GrayImage {
+    let (horizontal, vertical) = match operator {
+        GradientOperator::Sobel => (horizontal_sobel(image), vertical_sobel(image)),
+        GradientOperator::Scharr => (horizontal_scharr(image), vertical_scharr(image)),
+    };
+
+    let magnitude = FloatImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([f32::from(horizontal.get_pixel(x, y).0[0])
+            .hypot(f32::from(vertical.get_pixel(x, y).0[0]))])
+    });
+
+    normalize(&magnitude)
+}
+
+/// Scales the values of the image to 0-255 by the largest one
+fn normalize(image: &FloatImage) -> GrayImage {
+    let maximum = image
+        .pixels()
+        .fold(0.0, |maximum, pixel| pixel.0[0].max(maximum));
+
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        if maximum <= 0.0 {
+            Luma([0])
+        } else {
+            Luma([(255.0 * image.get_pixel(x, y).0[0] / maximum).round() as u8])
+        }
+    })
+}
+
+/// Finds the edges as the zero crossings of the Laplacian of Gaussian at several scales, keeping
+/// the crossings where the response changes by more than the threshold. The response is
+/// normalized by the square of the scale so the threshold applies equally to all of them
+fn laplacian_of_gaussian(
+    image: &GrayImage,
+    minimum_sigma: f32,
+    maximum_sigma: f32,
+    scales: u32,
+    threshold: f32,
+) -> (GrayImage, GrayImage) {
+    const LAPLACIAN: [f32; 9] = [0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0];
+
+    let minimum_sigma = minimum_sigma.max(0.1);
+    let maximum_sigma = maximum_sigma.max(minimum_sigma);
+    let scales = scales.max(1);
+
+    let source = FloatImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([f32::from(image.get_pixel(x, y).0[0])])
+    });
+
+    let mut edges: GrayImage = ImageBuffer::new(image.width(), image.height());
+    let mut strength = FloatImage::new(image.width(), image.height());
+    for i in 0..scales {
+        // Space the scales geometrically, as the structures they respond to grow with them
+        let sigma = if scales == 1 {
+            minimum_sigma
+        } else {
+            minimum_sigma * (maximum_sigma / minimum_sigma).powf(i as f32 / (scales - 1) as f32)
+        };
+
+        let response: FloatImage =
+            filter3x3::<_, f32, f32>(&gaussian_blur_f32(&source, sigma), &LAPLACIAN);
+
+        for (x, y, pixel) in response.enumerate_pixels() {
+            let value = pixel.0[0] * sigma * sigma;
+
+            let strongest = &mut strength.get_pixel_mut(x, y).0[0];
+            *strongest = strongest.max(value.abs());
+
+            // Compare with the neighbours to the right and below, so each crossing is found once
+            for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                if image.width() <= nx || image.height() <= ny {
+                    continue;
+                }
+
+                // The response is zero away from any structure, which isn't a crossing
+                let neighbour = response.get_pixel(nx, ny).0[0] * sigma * sigma;
+                if value * neighbour < 0.0 && threshold < (value - neighbour).abs() {
+                    edges.get_pixel_mut(x, y).0[0] = 255;
+                }
+            }
+        }
+    }
+
+    (edges, normalize(&strength))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::detect_edges;
+    use crate::configuration::{EdgeDetector, GradientOperator};
+
+    /// A vertical step from dark to light between the columns 19 and 20
+    fn step() -> GrayImage {
+        GrayImage::from_fn(40, 40, |x, _| Luma([if x < 20 { 50 } else { 200 }]))
+    }
+
+    /// The columns with an edge pixel, and whether every row has one apart from the first and
+    /// last (which Canny's detector leaves out)
+    fn columns(edges: &GrayImage) -> (Vec<u32>, bool) {
+        let mut columns: Vec<u32> = edges
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] != 0)
+            .map(|(x, _, _)| x)
+            .collect();
+        columns.sort_unstable();
+        columns.dedup();
+
+        let every_row = (1..edges.height() - 1)
+            .all(|y| (0..edges.width()).any(|x| edges.get_pixel(x, y).0[0] != 0));
+
+        (columns, every_row)
+    }
+
+    /// Asserts the edges follow the step along its entire length and nowhere else
+    fn assert_on_step(edges: &GrayImage) {
+        let (columns, every_row) = columns(edges);
+
+        assert!(every_row, "{columns:?}");
+        assert!(columns.iter().all(|x| (17..=22).contains(x)), "{columns:?}");
+    }
+
+    #[test]
+    fn contrast_finds_the_step() {
+        let (edges, _, level) = detect_edges(&step(), EdgeDetector::Contrast, 45.0);
+
+        assert_on_step(&edges);
+        assert_eq!(level, Some(45.0));
+    }
+
+    #[test]
+    fn canny_finds_the_step_with_swapped_thresholds() {
+        let canny = |low_threshold, high_threshold| {
+            detect_edges(
+                &step(),
+                EdgeDetector::Canny {
+                    low_threshold,
+                    high_threshold,
+                },
+                0.0,
+            )
+        };
+
+        let (edges, strength, level) = canny(20.0, 50.0);
+        assert_on_step(&edges);
+        assert_eq!(strength.get_pixel(0, 20).0[0], 0);
+        assert_eq!(level, None);
+        assert_eq!(canny(50.0, 20.0), (edges, strength, level));
+    }
+
+    #[test]
+    fn gradient_finds_the_step() {
+        for operator in [GradientOperator::Sobel, GradientOperator::Scharr] {
+            let (edges, strength, level) = detect_edges(
+                &step(),
+                EdgeDetector::Gradient {
+                    operator,
+                    blur: 1.0,
+                },
+                0.0,
+            );
+
+            assert_on_step(&edges);
+            assert_eq!(strength.pixels().map(|pixel| pixel.0[0]).max(), Some(255));
+
+            // The level reported is the one separating the edges from the background
+            let level = level.unwrap();
+            assert!(strength
+                .pixels()
+                .zip(edges.pixels())
+                .all(|(strength, edge)| (level < f32::from(strength.0[0])) == (edge.0[0] == 255)));
+        }
+    }
+
+    #[test]
+    fn laplacian_of_gaussian_finds_the_step_at_one_scale() {
+        let log = |maximum_sigma, scales| {
+            detect_edges(
+                &step(),
+                EdgeDetector::LaplacianOfGaussian {
+                    minimum_sigma: 1.5,
+                    maximum_sigma,
+                    scales,
+                    threshold: 5.0,
+                },
+                0.0,
+            )
+        };
+
+        let (edges, _, level) = log(1.5, 1);
+        assert_on_step(&edges);
+        assert_eq!(level, None);
+
+        // A single scale is the minimum one, whatever the maximum
+        assert_eq!(log(4.0, 1), log(1.5, 1));
+        assert_on_step(&log(4.0, 3).0);
+    }
+}
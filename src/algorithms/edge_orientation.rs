@@ -0,0 +1,138 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use image::{GrayImage, ImageBuffer, Luma};
+use imageproc::{
+    filter::gaussian_blur_f32,
+    gradients::{horizontal_sobel, vertical_sobel},
+};
+
+use crate::{
+    algorithms::Flake,
+    configuration::{AngleWeighting, OrientationSource},
+};
+
+/// An image of floating point values, such as a filter response
+type FloatImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// The number of steps the weights are rounded to, so the zone is found from a few distance
+/// transforms instead of one per edge pixel
+pub const WEIGHT_LEVELS: u8 = 15;
+
+/// The standard deviation of the window the orientation is averaged over (px), which also gives
+/// the pixels inside thick edges the orientation of their border
+const ORIENTATION_SIGMA: f32 = 2.0;
+
+/// Weights every edge pixel by the angle between the edge and the surface normal (degrees), from
+/// 0 for edges that don't exclude bacteria to [`WEIGHT_LEVELS`] for edges with the full radius
+#[allow(clippy::too_many_arguments)]
+pub fn edge_weights(
+    image: &GrayImage,
+    edges: &GrayImage,
+    flakes: &[Flake],
+    orientation: OrientationSource,
+    surface_normal: f32,
+    tolerance: f32,
+    weighting: AngleWeighting,
+    scale: f32,
+) -> GrayImage {
+    let local = local_orientation(image);
+
+    let mut weights = GrayImage::new(edges.width(), edges.height());
+    for (x, y, pixel) in edges.enumerate_pixels() {
+        if pixel.0[0] == 0 {
+            continue;
+        }
+
+        let direction = match orientation {
+            OrientationSource::Local => None,
+            OrientationSource::Flakes => flake_direction(flakes, x, y, scale),
+        }
+        .unwrap_or_else(|| local.get_pixel(x, y).0[0]);
+
+        let angle = angle_between(direction, surface_normal.to_radians());
+        let weight = if angle <= tolerance.to_radians() {
+            match weighting {
+                AngleWeighting::Restrict => 1.0,
+                AngleWeighting::Cosine => angle.cos(),
+            }
+        } else {
+            0.0
+        };
+
+        weights.put_pixel(
+            x,
+            y,
+            Luma([(weight * f32::from(WEIGHT_LEVELS)).round() as u8]),
+        );
+    }
+
+    weights
+}
+
+/// The direction of the edges through every pixel in radians, from the structure tensor of the
+/// image. The gradient is perpendicular to the edge, and averaging the tensor instead of the
+/// gradient keeps opposite gradients on the two sides of a flake from cancelling out
+fn local_orientation(image: &GrayImage) -> FloatImage {
+    let horizontal = horizontal_sobel(image);
+    let vertical = vertical_sobel(image);
+
+    let product = |f: fn(f32, f32) -> f32| {
+        FloatImage::from_fn(image.width(), image.height(), |x, y| {
+            Luma([f(
+                f32::from(horizontal.get_pixel(x, y).0[0]),
+                f32::from(vertical.get_pixel(x, y).0[0]),
+            )])
+        })
+    };
+
+    let xx = gaussian_blur_f32(&product(|gx, _| gx * gx), ORIENTATION_SIGMA);
+    let xy = gaussian_blur_f32(&product(|gx, gy| gx * gy), ORIENTATION_SIGMA);
+    let yy = gaussian_blur_f32(&product(|_, gy| gy * gy), ORIENTATION_SIGMA);
+
+    FloatImage::from_fn(image.width(), image.height(), |x, y| {
+        let gradient = 0.5
+            * (2.0 * xy.get_pixel(x, y).0[0])
+                .atan2(xx.get_pixel(x, y).0[0] - yy.get_pixel(x, y).0[0]);
+
+        Luma([gradient + FRAC_PI_2])
+    })
+}
+
+/// The direction of the closest flake covering the pixel, where a flake covers the pixels
+/// within half its length of its center
+fn flake_direction(flakes: &[Flake], x: u32, y: u32, scale: f32) -> Option<f32> {
+    flakes
+        .iter()
+        .map(|flake| {
+            let distance = (flake.center.0 - x as f32).hypot(flake.center.1 - y as f32);
+            (distance, flake)
+        })
+        .filter(|(distance, flake)| *distance <= flake.length / scale / 2.0)
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        // The angle of a flake is PI/2 minus the direction of the line between its furthest
+        // points
+        .map(|(_, flake)| FRAC_PI_2 - flake.angle)
+}
+
+/// The angle between two directions in radians (0 to PI/2), where opposite directions are the
+/// same
+fn angle_between(a: f32, b: f32) -> f32 {
+    let difference = (a - b).rem_euclid(PI);
+
+    difference.min(PI - difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    use super::angle_between;
+
+    #[test]
+    fn opposite_directions_are_the_same() {
+        assert!(angle_between(0.0, PI).abs() < 1e-5);
+        assert!(angle_between(-FRAC_PI_2, FRAC_PI_2).abs() < 1e-5);
+        assert!((angle_between(0.0, FRAC_PI_2) - FRAC_PI_2).abs() < 1e-5);
+        assert!((angle_between(3.0 * FRAC_PI_4, 0.0) - FRAC_PI_4).abs() < 1e-5);
+    }
+}
@@ -1,20 +1,95 @@
-use image::{DynamicImage, GrayImage};
+use image::{DynamicImage, GrayImage, RgbImage};
 use imageproc::{contours, contrast, drawing, filter, point::Point};
 use plotters::prelude::{
     BitMapBackend, ChartBuilder, Circle, Color, IntoDrawingArea, Rectangle, BLACK, WHITE,
 };
+use serde::Serialize;
 
-use std::{f32::consts, fs, ops::Range};
+use std::{f32::consts, ops::Range};
 
-use crate::configuration::GrapheneAngles;
+use crate::{
+    artifacts::{Artifact, ArtifactError, ArtifactSink, Table},
+    configuration::GrapheneAngles,
+};
+
+/// The size of the exported plots in pixels
+const PLOT_WIDTH: u32 = 640;
+const PLOT_HEIGHT: u32 = 480;
+
+/// A graphene flake found in the image
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Flake {
+    /// The center of the flake in pixels
+    pub center: (f32, f32),
+    /// The angle of the normal of the flake in radians, zero is horizontal and PI/2 is vertical
+    pub angle: f32,
+    /// The length of the flake in micrometers
+    pub length: f32,
+}
+
+/// Summary statistics of the flakes in an image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FlakeStatistics {
+    pub count: usize,
+    /// The mean length in micrometers
+    pub mean_length: f32,
+    /// The sample standard deviation of the length in micrometers
+    pub length_standard_deviation: f32,
+    /// The mean angle of the normals in radians, where opposite directions are the same
+    pub mean_angle: f32,
+    /// How aligned the flakes are, from 0 for random orientations to 1 when all are parallel
+    /// (the nematic order parameter)
+    pub order_parameter: f32,
+}
+
+impl FlakeStatistics {
+    /// Calculates the statistics of the flakes, if there are any
+    pub fn new(flakes: &[Flake]) -> Option<Self> {
+        if flakes.is_empty() {
+            return None;
+        }
+
+        let count = flakes.len() as f32;
+        let mean_length = flakes.iter().map(|flake| flake.length).sum::<f32>() / count;
+        let length_standard_deviation = if flakes.len() < 2 {
+            0.0
+        } else {
+            (flakes
+                .iter()
+                .map(|flake| (flake.length - mean_length).powi(2))
+                .sum::<f32>()
+                / (count - 1.0))
+                .sqrt()
+        };
+
+        // Angles are doubled so that opposite directions of the normal coincide
+        let cos = flakes
+            .iter()
+            .map(|flake| (2.0 * flake.angle).cos())
+            .sum::<f32>()
+            / count;
+        let sin = flakes
+            .iter()
+            .map(|flake| (2.0 * flake.angle).sin())
+            .sum::<f32>()
+            / count;
+
+        Some(FlakeStatistics {
+            count: flakes.len(),
+            mean_length,
+            length_standard_deviation,
+            mean_angle: sin.atan2(cos) / 2.0,
+            order_parameter: cos.hypot(sin),
+        })
+    }
+}
 
 pub fn graphene_angles(
     input_image: &GrayImage,
     config: &GrapheneAngles,
     scale: f32,
-    debug: bool,
-    output_prefix: &str,
-) -> Vec<f32> {
+    artifacts: &dyn ArtifactSink,
+) -> Result<Vec<Flake>, ArtifactError> {
     // Blur and threshold the images to extract features from the background
     let mut mask = filter::gaussian_blur_f32(input_image, config.blur);
     contrast::threshold_mut(&mut mask, config.threshold);
@@ -22,12 +97,12 @@ pub fn graphene_angles(
     // Find the contours in the mask (should be contours of graphene)
     let contours: Vec<contours::Contour<u32>> = contours::find_contours(&mask);
 
-    // Create a output image used in debug mode
+    // Create a output image visualizing the flakes
+    let debug = artifacts.accepts("angles.png");
     let mut furthest_points = DynamicImage::ImageLuma8(input_image.clone()).into_rgb8();
 
     // Find the average normal for every contour and display it using arrows
-    let mut points_and_angles = Vec::new();
-    let mut lengths = Vec::new();
+    let mut flakes = Vec::new();
     for contour in contours {
         // The normal of the line connecting the two furthest point in the contours is the angle of
         // the graphene flake, here we test only a subset of points to improve performance
@@ -46,8 +121,8 @@ pub fn graphene_angles(
         let mut current_maximum_distance = 0.0;
         for sample_p1 in &sample_points {
             for sample_p2 in &sample_points {
-                let distance = ((sample_p1.x - sample_p2.x).pow(2) as f32
-                    + (sample_p1.y - sample_p2.y).pow(2) as f32)
+                let distance = (sample_p1.x.abs_diff(sample_p2.x).pow(2) as f32
+                    + sample_p1.y.abs_diff(sample_p2.y).pow(2) as f32)
                     .sqrt();
 
                 if current_maximum_distance < distance {
@@ -98,8 +173,11 @@ pub fn graphene_angles(
                 .atan2(point_2.x as f32 - point_1.x as f32)
                 .rem_euclid(consts::PI);
 
-        points_and_angles.push(((center_x, center_y), angle));
-        lengths.push(current_maximum_distance * scale);
+        flakes.push(Flake {
+            center: (center_x, center_y),
+            angle,
+            length: current_maximum_distance * scale,
+        });
 
         if debug {
             // Draw the two furthest points
@@ -157,62 +235,77 @@ pub fn graphene_angles(
     }
 
     if debug {
-        furthest_points
-            .save(output_prefix.to_string() + "angles.png")
-            .unwrap();
+        artifacts.emit("angles.png", Artifact::Image(furthest_points.into()))?;
     }
 
-    // Vector of angles without radial length
-    let angles: Vec<_> = points_and_angles.iter().map(|(_, a)| *a).collect();
+    // Vectors of angles and lengths for the plots
+    let angles: Vec<_> = flakes.iter().map(|flake| flake.angle).collect();
+    let lengths: Vec<_> = flakes.iter().map(|flake| flake.length).collect();
 
-    // Plot the histograms and export to a CSV files
-    plot_angle_histogram(&angles, output_prefix);
-    plot_length_histogram(&lengths, output_prefix);
-    plot_angle_length_scatterplot(&angles, &lengths, output_prefix);
+    // Plot the histograms
+    if artifacts.accepts("angle-histogram.png") {
+        artifacts.emit(
+            "angle-histogram.png",
+            Artifact::Image(plot_angle_histogram(&angles).into()),
+        )?;
+    }
 
-    // Save the angles as a CSV file
-    let mut csv = csv::Writer::from_writer(
-        fs::File::create(output_prefix.to_string() + "angles.csv")
-            .expect("Failed to open CSV file"),
-    );
-
-    // Write header to file
-    csv.write_record(["radial_distance", "angle"]).unwrap();
-
-    for ((x, y), angle) in &points_and_angles {
-        // The rounded distance in pixel from the current point (center of flake) to the center of
-        // the radial sample
-        let distance = ((input_image.width() as f32 - *x).powi(2)
-            + (*y - input_image.height() as f32 / 2.0).powi(2))
-        .sqrt()
-        .round();
-
-        csv.write_record(&[
-            format!("{}", (distance as f32) * scale),
-            format!("{:.3}", angle.to_degrees()),
-        ])
-        .expect("Failed to write angles");
+    if artifacts.accepts("length-histogram.png") {
+        artifacts.emit(
+            "length-histogram.png",
+            Artifact::Image(plot_length_histogram(&lengths).into()),
+        )?;
     }
 
-    csv.flush().unwrap();
+    if artifacts.accepts("angle-length-scatterplot.png") {
+        artifacts.emit(
+            "angle-length-scatterplot.png",
+            Artifact::Image(plot_angle_length_scatterplot(&angles, &lengths).into()),
+        )?;
+    }
 
-    // Save the lengths as a CSV file
-    let mut csv = csv::Writer::from_writer(
-        fs::File::create(output_prefix.to_string() + "lengths.csv")
-            .expect("Failed to open CSV file"),
-    );
-
-    for length in &lengths {
-        csv.write_record(&[format!("{:.3}", length)])
-            .expect("Failed to write lengths");
+    // Save the angles as a CSV file
+    if artifacts.accepts("angles.csv") {
+        let mut table = Table {
+            header: vec!["radial_distance".to_string(), "angle".to_string()],
+            rows: Vec::new(),
+        };
+
+        for flake in &flakes {
+            // The rounded distance in pixel from the current point (center of flake) to the
+            // center of the radial sample
+            let (x, y) = flake.center;
+            let distance = ((input_image.width() as f32 - x).powi(2)
+                + (y - input_image.height() as f32 / 2.0).powi(2))
+            .sqrt()
+            .round();
+
+            table.rows.push(vec![
+                format!("{}", distance * scale),
+                format!("{:.3}", flake.angle.to_degrees()),
+            ]);
+        }
+
+        artifacts.emit("angles.csv", Artifact::Table(table))?;
     }
 
-    csv.flush().unwrap();
+    // Save the lengths as a CSV file
+    if artifacts.accepts("lengths.csv") {
+        let table = Table {
+            header: Vec::new(),
+            rows: lengths
+                .iter()
+                .map(|length| vec![format!("{length:.3}")])
+                .collect(),
+        };
 
-    angles
+        artifacts.emit("lengths.csv", Artifact::Table(table))?;
+    }
+
+    Ok(flakes)
 }
 
-fn plot_length_histogram(lengths: &[f32], output_prefix: &str) {
+fn plot_length_histogram(lengths: &[f32]) -> RgbImage {
     let mut max_length = 0.0;
     for length in lengths {
         max_length = length.max(max_length);
@@ -224,14 +317,11 @@ fn plot_length_histogram(lengths: &[f32], output_prefix: &str) {
         25,
         "Length (μm)",
         "Count (number of flakes)",
-        output_prefix
-            .trim_start_matches("./output/")
-            .trim_end_matches('_'),
-        &(output_prefix.to_string() + "length-histogram.png"),
-    );
+        "Flake length distribution",
+    )
 }
 
-fn plot_angle_histogram(angles: &[f32], output_prefix: &str) {
+fn plot_angle_histogram(angles: &[f32]) -> RgbImage {
     // Convert all the angles to degrees centered at 0, between -90 and 90
     let mut scaled_angles = Vec::new();
     for angle in angles {
@@ -244,14 +334,11 @@ fn plot_angle_histogram(angles: &[f32], output_prefix: &str) {
         25,
         "Direction (°)",
         "Count (number of flakes)",
-        output_prefix
-            .trim_start_matches("./output/")
-            .trim_end_matches('_'),
-        &(output_prefix.to_string() + "angle-histogram.png"),
-    );
+        "Flake orientation distribution",
+    )
 }
 
-fn plot_angle_length_scatterplot(raw_angles: &[f32], lengths: &[f32], output_prefix: &str) {
+fn plot_angle_length_scatterplot(raw_angles: &[f32], lengths: &[f32]) -> RgbImage {
     // Convert all the angles to degrees centered at 0, between -90 and 90
     let mut angles = Vec::new();
     for angle in raw_angles {
@@ -264,47 +351,47 @@ fn plot_angle_length_scatterplot(raw_angles: &[f32], lengths: &[f32], output_pre
         max_length = length.max(max_length);
     }
 
-    // Find the largest and smallest for both axis
-    let caption = output_prefix
-        .trim_start_matches("./output/")
-        .trim_end_matches('_');
-
-    let filepath = output_prefix.to_string() + "angle-length-scatterplot.png";
-    let canvas = BitMapBackend::new(&filepath, (640, 480)).into_drawing_area();
-    canvas.fill(&WHITE).unwrap();
-
-    // Create a chart with a caption
-    let mut chart = ChartBuilder::on(&canvas)
-        .x_label_area_size(35)
-        .y_label_area_size(40)
-        .caption(caption, ("sans-serif", 30))
-        .margin(15)
-        .build_cartesian_2d(-90.0..90.0_f32, 0.0..max_length)
-        .unwrap();
-
-    // Add X and Y labels to the chart
-    chart
-        .configure_mesh()
-        .disable_x_mesh()
-        .disable_y_mesh()
-        .bold_line_style(WHITE.mix(0.3))
-        .x_desc("Angle (°)")
-        .y_desc("Length (μm)")
-        .axis_desc_style(("sans-serif", 15))
-        .draw()
-        .unwrap();
-
-    chart
-        .draw_series(
-            angles
-                .iter()
-                .zip(lengths.iter())
-                .map(|(angle, length)| Circle::new((*angle, *length), 5, BLACK.filled())),
-        )
-        .unwrap();
+    // Draw the plot into an in memory buffer
+    let mut buffer = vec![0; (PLOT_WIDTH * PLOT_HEIGHT * 3) as usize];
+    {
+        let canvas =
+            BitMapBackend::with_buffer(&mut buffer, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area();
+        canvas.fill(&WHITE).unwrap();
+
+        // Create a chart with a caption
+        let mut chart = ChartBuilder::on(&canvas)
+            .x_label_area_size(35)
+            .y_label_area_size(40)
+            .caption("Flake length by orientation", ("sans-serif", 30))
+            .margin(15)
+            .build_cartesian_2d(-90.0..90.0_f32, 0.0..max_length)
+            .unwrap();
+
+        // Add X and Y labels to the chart
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .disable_y_mesh()
+            .bold_line_style(WHITE.mix(0.3))
+            .x_desc("Angle (°)")
+            .y_desc("Length (μm)")
+            .axis_desc_style(("sans-serif", 15))
+            .draw()
+            .unwrap();
+
+        chart
+            .draw_series(
+                angles
+                    .iter()
+                    .zip(lengths.iter())
+                    .map(|(angle, length)| Circle::new((*angle, *length), 5, BLACK.filled())),
+            )
+            .unwrap();
+
+        canvas.present().expect("Failed to draw plot");
+    }
 
-    // Export the plot
-    canvas.present().expect("Failed to save plot to file");
+    RgbImage::from_raw(PLOT_WIDTH, PLOT_HEIGHT, buffer).expect("The plot buffer has the wrong size")
 }
 
 fn plot_histogram(
@@ -314,8 +401,7 @@ fn plot_histogram(
     x_desc: &str,
     y_desc: &str,
     caption: &str,
-    filepath: &str,
-) {
+) -> RgbImage {
     let bucket_size = (range.end - range.start) / bucket_count as f32;
 
     // Calculate frequency for every bucket
@@ -332,51 +418,56 @@ fn plot_histogram(
         }
     }
 
-    // Create a blank canvas with a white background
-    let canvas = BitMapBackend::new(&filepath, (640, 480)).into_drawing_area();
-    canvas.fill(&WHITE).unwrap();
-
-    // Create a chart with a caption
-    let mut chart = ChartBuilder::on(&canvas)
-        .x_label_area_size(35)
-        .y_label_area_size(40)
-        .caption(caption, ("sans-serif", 30))
-        .margin(15)
-        .build_cartesian_2d(
-            range.clone(),
-            0..(buckets[largest_bucket] as f32 * 1.2).round() as usize,
-        )
-        .unwrap();
-
-    // Add X and Y labels to the chart
-    chart
-        .configure_mesh()
-        .disable_x_mesh()
-        .disable_y_mesh()
-        .bold_line_style(WHITE.mix(0.3))
-        .x_desc(x_desc)
-        .y_desc(y_desc)
-        .axis_desc_style(("sans-serif", 15))
-        .draw()
-        .unwrap();
-
-    // Draw the histogram using the frequencies
-    chart
-        .draw_series((0..).zip(buckets.iter()).map(|(x, y)| {
-            let mut bar = Rectangle::new(
-                [
-                    (x as f32 * bucket_size + range.start, 0),
-                    ((x + 1) as f32 * bucket_size + range.start, *y),
-                ],
-                BLACK.filled(),
-            );
+    // Create a blank canvas with a white background in an in memory buffer
+    let mut buffer = vec![0; (PLOT_WIDTH * PLOT_HEIGHT * 3) as usize];
+    {
+        let canvas =
+            BitMapBackend::with_buffer(&mut buffer, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area();
+        canvas.fill(&WHITE).unwrap();
+
+        // Create a chart with a caption
+        let mut chart = ChartBuilder::on(&canvas)
+            .x_label_area_size(35)
+            .y_label_area_size(40)
+            .caption(caption, ("sans-serif", 30))
+            .margin(15)
+            .build_cartesian_2d(
+                range.clone(),
+                0..(buckets[largest_bucket] as f32 * 1.2).round() as usize,
+            )
+            .unwrap();
 
-            bar.set_margin(0, 0, 2, 0);
+        // Add X and Y labels to the chart
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .disable_y_mesh()
+            .bold_line_style(WHITE.mix(0.3))
+            .x_desc(x_desc)
+            .y_desc(y_desc)
+            .axis_desc_style(("sans-serif", 15))
+            .draw()
+            .unwrap();
+
+        // Draw the histogram using the frequencies
+        chart
+            .draw_series((0..).zip(buckets.iter()).map(|(x, y)| {
+                let mut bar = Rectangle::new(
+                    [
+                        (x as f32 * bucket_size + range.start, 0),
+                        ((x + 1) as f32 * bucket_size + range.start, *y),
+                    ],
+                    BLACK.filled(),
+                );
+
+                bar.set_margin(0, 0, 2, 0);
+
+                bar
+            }))
+            .unwrap();
 
-            bar
-        }))
-        .unwrap();
+        canvas.present().expect("Failed to draw plot");
+    }
 
-    // Export the plot
-    canvas.present().expect("Failed to save plot to file");
+    RgbImage::from_raw(PLOT_WIDTH, PLOT_HEIGHT, buffer).expect("The plot buffer has the wrong size")
 }
@@ -1,5 +1,10 @@
 use image::{GrayImage, ImageBuffer, Luma};
-use imageproc::{contours, drawing::draw_polygon_mut, point::Point};
+use imageproc::{
+    contours,
+    drawing::draw_polygon_mut,
+    point::Point,
+    region_labelling::{connected_components, Connectivity},
+};
 
 /// Helper function that removes all contours that have to few pixels (determined by the minimum
 /// area)
@@ -43,6 +48,28 @@ pub fn filter_by_minimum_area(mask: &GrayImage, minimum_area: usize) -> GrayImag
     denoised_mask
 }
 
+/// Helper function that removes all connected regions with to few pixels, for thin lines which
+/// have no area as a polygon
+pub fn filter_by_minimum_size(mask: &GrayImage, minimum_size: usize) -> GrayImage {
+    let labels = connected_components(mask, Connectivity::Eight, Luma([0]));
+
+    // Count the pixels of every region, the background is label 0
+    let mut sizes =
+        vec![0; labels.pixels().map(|label| label.0[0]).max().unwrap_or(0) as usize + 1];
+    for label in labels.pixels() {
+        sizes[label.0[0] as usize] += 1;
+    }
+
+    GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let label = labels.get_pixel(x, y).0[0];
+        if label != 0 && minimum_size < sizes[label as usize] {
+            Luma([255])
+        } else {
+            Luma([0])
+        }
+    })
+}
+
 /// Helper that calculates the thresholded absolute contrast of a input image. Returns (threshold,
 /// contrast)
 ///
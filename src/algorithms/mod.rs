@@ -1,23 +1,41 @@
 use std::fmt;
 
+use crate::artifacts::ArtifactError;
+
+mod bacteria_detection;
 mod bacteria_exclusion;
+mod bacterium_placement;
+mod character_recognition;
+mod edge_detection;
+mod edge_orientation;
 mod graphene_angles;
 mod helpers;
 mod pre_processing;
 mod text_recognition;
+mod thresholding;
 
 // Rexport all functions
-pub use bacteria_exclusion::bacteria_exclusion;
-pub use graphene_angles::graphene_angles;
+pub use bacteria_detection::{bacteria_detection, Bacterium, Detection};
+pub use bacteria_exclusion::{
+    bacteria_exclusion, exclusion_zone, graphene_edges, Edges, Exclusion,
+};
+pub use character_recognition::{recognize_label, Recognition};
+pub use edge_detection::detect_edges;
+pub use graphene_angles::{graphene_angles, Flake, FlakeStatistics};
 pub use pre_processing::pre_processing;
-pub use text_recognition::determine_scale;
+pub use text_recognition::{determine_scale, Scale, ScaleSource};
+pub use thresholding::select_threshold;
 
 #[derive(Debug)]
 pub enum Error {
     FailedToDetectText(String),
+    TesseractUnavailable(String),
+    UnknownFooterProfile(String),
+    LowScaleConfidence(f32),
     ToSmallExclusionDiameter,
     ExtremeLineIsNonHorizontal,
     LessThenTwoApplicableLinesFound,
+    Artifact(ArtifactError),
 }
 
 impl fmt::Display for Error {
@@ -27,12 +45,22 @@ impl fmt::Display for Error {
             "{}",
             match self {
                 Error::FailedToDetectText(detected) => format!("Couldn't detect the scale using OCR (detected: {detected})"),
+                Error::TesseractUnavailable(e) => format!("Couldn't run Tesseract, is it on path and installed? ({e})"),
+                Error::UnknownFooterProfile(name) => format!("There is no footer profile named {name:?}"),
+                Error::LowScaleConfidence(confidence) => format!("The confidence in the detected scale is too low ({confidence:.2})"),
                 Error::ToSmallExclusionDiameter => "The bacteria exclusion diameter is smaller than 1 pixel which effectively makes it non-existent".to_string(),
                 Error::ExtremeLineIsNonHorizontal => "The two lines creating the scale are not on the same y level".to_string(),
                 Error::LessThenTwoApplicableLinesFound => "Less then two lines that meet the requirements were found when trying to detect scale".to_string(),
+                Error::Artifact(e) => e.to_string(),
             }
         )
     }
 }
 
 impl std::error::Error for Error {}
+
+impl From<ArtifactError> for Error {
+    fn from(e: ArtifactError) -> Self {
+        Error::Artifact(e)
+    }
+}
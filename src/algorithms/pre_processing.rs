@@ -6,7 +6,7 @@ use crate::configuration::PreProcessing;
 pub fn pre_processing(mut input_image: GrayImage, config: PreProcessing) -> GrayImage {
     if config.equalize_histogram {
         equalize_histogram_mut(&mut input_image);
-    };
+    }
 
     input_image
 }
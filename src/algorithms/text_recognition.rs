@@ -1,25 +1,115 @@
 use image::{imageops, DynamicImage, GrayImage, Luma, RgbImage};
 use imageproc::{contours, contrast, drawing, filter};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    process::{self, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    algorithms::{character_recognition::recognize_label, Error},
+    artifacts::{Artifact, ArtifactSink},
+    configuration::{
+        ConfidencePolicy, FooterSide, OcrEngine, Polarity, TextPosition, TextRecognition,
+    },
+    input::{PixelSize, Vendor},
+    units::micrometers_per_unit,
+};
+
+/// The scale of an image as read from the scale bar in the footer
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Scale {
+    /// The size of a pixel (um / px)
+    pub scale: f32,
+    /// The length of the scale bar in micrometers
+    pub micrometers: f32,
+    /// The length of the scale bar in pixels
+    pub pixels: u32,
+    /// The height of the footer containing the scale bar in pixels
+    pub scale_bar_height: u32,
+    /// Where the scale came from
+    pub source: ScaleSource,
+    /// How reliable the detected scale is from 0 to 1, always 1 for the override and metadata.
+    /// When falling back to the override this is the confidence of the rejected scale
+    pub confidence: f32,
+}
 
-use std::{collections::HashMap, process::Command};
+/// The source of the scale of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleSource {
+    /// The override values in the configuration
+    Override,
+    /// The pixel size stored by the microscope in the image metadata
+    Metadata(Vendor),
+    /// Text recognition of the scale bar label
+    TextRecognition,
+}
 
-use crate::{algorithms::Error, configuration::TextRecognition};
+impl Serialize for ScaleSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
+impl<'de> Deserialize<'de> for ScaleSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+
+        match source.as_str() {
+            "override" => Ok(ScaleSource::Override),
+            "metadata (Zeiss)" => Ok(ScaleSource::Metadata(Vendor::Zeiss)),
+            "metadata (FEI)" => Ok(ScaleSource::Metadata(Vendor::Fei)),
+            "text recognition" => Ok(ScaleSource::TextRecognition),
+            _ => Err(de::Error::custom(format!(
+                "unknown scale source {source:?}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ScaleSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScaleSource::Override => write!(f, "override"),
+            ScaleSource::Metadata(vendor) => write!(f, "metadata ({vendor})"),
+            ScaleSource::TextRecognition => write!(f, "text recognition"),
+        }
+    }
+}
+
+/// Counter making the names of temporary files unique within the process
+static TEMPORARY_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Determines the scale of the image and returns it together with the image without the footer.
+/// The pixel size from the image metadata is used if available, otherwise the scale bar is read
 pub fn determine_scale(
     mut input_image: GrayImage,
     config: &TextRecognition,
-    debug: bool,
-    output_prefix: &str,
-) -> Result<(f32, f32, u32, u32, GrayImage), Error> {
-    const SKIP_BOTTOM_PIXELS: u32 = 40;
+    pixel_size: Option<PixelSize>,
+    magnification: Option<f32>,
+    artifacts: &dyn ArtifactSink,
+) -> Result<(Scale, GrayImage), Error> {
+    let profile = config
+        .footer_profile()
+        .ok_or_else(|| Error::UnknownFooterProfile(config.profile.clone()))?;
 
     let width = input_image.width();
     let height = input_image.height();
 
+    // The footer is analysed with a light bar and label, so dark ones are inverted
+    let mut footer_image = input_image.clone();
+    if profile.polarity == Polarity::Dark {
+        imageops::invert(&mut footer_image);
+    }
+
     let scale_bar_height = if config.override_scale {
         height - config.scale_bar_height
     } else {
-        // Show all the heights if debug
+        // Show all the heights if requested
+        let debug = artifacts.accepts("heights.png");
         let mut visualized_heights: RgbImage =
             DynamicImage::ImageLuma8(input_image.clone()).into_rgb8();
 
@@ -27,10 +117,11 @@ pub fn determine_scale(
         // of all non-white pixels in each row. Skip the first n pixels to try to skip white letters
         let mut heights = HashMap::new();
         for x in 0..width {
+            let skip_bottom_pixels = profile.skip_bottom_pixels.min(height - 1);
             let mut previous_brightness =
-                input_image.get_pixel(x, height - SKIP_BOTTOM_PIXELS - 1).0[0];
-            for y in (0..height - SKIP_BOTTOM_PIXELS).rev() {
-                let brightness = input_image.get_pixel(x, y).0[0];
+                footer_image.get_pixel(x, height - skip_bottom_pixels - 1).0[0];
+            for y in (0..height - skip_bottom_pixels).rev() {
+                let brightness = footer_image.get_pixel(x, y).0[0];
 
                 // Ignore completely white pixels, or pixels where the previous pixel were brighter
                 if 200 < previous_brightness || brightness < previous_brightness {
@@ -68,9 +159,7 @@ pub fn determine_scale(
 
         // Export the debug image
         if debug {
-            visualized_heights
-                .save(output_prefix.to_string() + "heights.png")
-                .unwrap();
+            artifacts.emit("heights.png", Artifact::Image(visualized_heights.into()))?;
         }
 
         scale_bar_height
@@ -83,24 +172,49 @@ pub fn determine_scale(
     // If scale is overwritten return early
     if config.override_scale {
         return Ok((
-            config.override_scale_micrometers / (config.override_scale_pixels as f32),
-            config.override_scale_micrometers,
-            config.override_scale_pixels,
-            config.scale_bar_height,
+            Scale {
+                scale: config.override_scale_micrometers / (config.override_scale_pixels as f32),
+                micrometers: config.override_scale_micrometers,
+                pixels: config.override_scale_pixels,
+                scale_bar_height: config.scale_bar_height,
+                source: ScaleSource::Override,
+                confidence: 1.0,
+            },
+            image_without_text,
+        ));
+    }
+
+    // Use the pixel size stored by the microscope if available. As there is no scale bar
+    // involved the scale is described by the width of the entire image
+    if let Some(pixel_size) = pixel_size.filter(|_| config.use_metadata) {
+        return Ok((
+            Scale {
+                scale: pixel_size.micrometers,
+                micrometers: pixel_size.micrometers * width as f32,
+                pixels: width,
+                scale_bar_height: height - scale_bar_height,
+                source: ScaleSource::Metadata(pixel_size.vendor),
+                confidence: 1.0,
+            },
             image_without_text,
         ));
     }
 
-    // Retrieve the bottom part of the image and make it black and white (by threshold)
+    // Retrieve the part of the footer with the scale bar and make it black and white (by threshold)
+    let region_width = profile.region_width.min(width);
+    let region_x = match profile.side {
+        FooterSide::Left => 0,
+        FooterSide::Right => width - region_width,
+    };
     let mut image = imageops::crop(
-        &mut input_image,
-        width - 650,
+        &mut footer_image,
+        region_x,
         scale_bar_height,
-        650,
+        region_width,
         height - scale_bar_height,
     )
     .to_image();
-    contrast::threshold_mut(&mut image, 240);
+    contrast::threshold_mut(&mut image, profile.threshold);
 
     // Erode the image to remove lines that are 1 pixel thick (as in some images)
     //morphology::open_mut(&mut image, distance_transform::Norm::LInf, 1);
@@ -141,7 +255,7 @@ pub fn determine_scale(
                     continue;
                 }
 
-                if debug {
+                if artifacts.accepts("lines.png") {
                     drawing::draw_line_segment_mut(
                         &mut image,
                         (minimum_point.x as f32, minimum_point.y as f32),
@@ -166,8 +280,11 @@ pub fn determine_scale(
         }
     }
 
-    if debug {
-        image.save(output_prefix.to_string() + "lines.png").unwrap();
+    if artifacts.accepts("lines.png") {
+        artifacts.emit(
+            "lines.png",
+            Artifact::Image(DynamicImage::ImageLuma8(image)),
+        )?;
     }
 
     // Make sure that at least two lines accepted were found
@@ -184,60 +301,247 @@ pub fn determine_scale(
     let pixel_distance = maximum_line.0 - minimum_line.0;
 
     // Crop the image to extract just the text part to make it as easy as possible
-    // for Tesseract to recognize the text. Threshold it at the same time
+    // to recognize the text. Threshold it at the same time
+    let (text_start, text_end) = match profile.text_position {
+        TextPosition::Between => (minimum_line.1 + 2, maximum_line.1.saturating_sub(2)),
+        TextPosition::Outside => (minimum_line.0, maximum_line.0),
+    };
     let mut image = imageops::crop(
-        &mut input_image,
-        width - 650 + minimum_line.1 + 2,
-        scale_bar_height + maximum_line.2 - 18,
-        maximum_line.1 - minimum_line.1 - 4,
-        45,
+        &mut footer_image,
+        region_x + text_start,
+        (scale_bar_height + maximum_line.2).saturating_add_signed(-profile.text_offset),
+        text_end.saturating_sub(text_start).max(1),
+        profile.text_height,
     )
     .to_image();
-    contrast::threshold_mut(&mut image, 240);
-    let image = filter::gaussian_blur_f32(&image, 1.0);
+    contrast::threshold_mut(&mut image, profile.threshold);
 
-    // A bit of a hack but here we export the image to a file so that Tesseract then
-    // easily can analyse it
-    let scale_path = output_prefix.to_string() + "scale.png";
-    image.save(&scale_path).unwrap();
-
-    // Run command line Tesseract to be platform agnostic
-    let text = std::str::from_utf8(
-        &Command::new("tesseract")
-            .args([
-                &scale_path,
-                "stdout",
-                "-l",
-                "eng",
-                "--psm",
-                "7",
-                "-c",
-                "tessedit_char_whitelist=\"1234567890um\"",
-            ])
-            .output()
-            .expect("Couldn't run Tesseract, is it on path and installed?")
-            .stdout,
-    )
-    .unwrap()
-    .to_string();
-
-    // The text should end with "um" (or "mm"), start with a combination of digits and optionally
-    // whitespace in between or on the outside of the string. This is validated below
-    let micrometer_distance: f32 = if let Some(digits) = text.trim().strip_suffix("um") {
-        match digits.trim().parse::<f32>() {
-            Ok(distance) => distance,
-            Err(_) => return Err(Error::FailedToDetectText(text.clone())),
+    let (text, text_confidence) = match config.engine {
+        OcrEngine::Tesseract => run_tesseract(&image)?,
+        OcrEngine::Builtin => {
+            let recognition = recognize_label(&image);
+            (recognition.text, recognition.confidence)
         }
-    } else {
+    };
+
+    if artifacts.accepts("scale.png") {
+        artifacts.emit(
+            "scale.png",
+            Artifact::Image(DynamicImage::ImageLuma8(image)),
+        )?;
+    }
+
+    // The text should be a number followed by a unit, which is validated and converted below
+    let Some(micrometer_distance) = parse_label(&text) else {
         return Err(Error::FailedToDetectText(text.clone()));
     };
 
+    let scale = micrometer_distance / (pixel_distance as f32);
+
+    // The pixel size implied by the magnification, which shows the width of the image at the
+    // reference width
+    let magnified = magnification
+        .zip(config.magnification_reference)
+        .map(|(magnification, reference)| reference * 1000.0 / magnification / width as f32);
+
+    // Combine the confidence of the text with how straight the bar is, how plausible the label
+    // and bar length are, and whether the scale agrees with the metadata (when not used) and
+    // the magnification
+    let straightness =
+        1.0 - (maximum_line.2.abs_diff(minimum_line.2) * 20) as f32 / pixel_distance.max(1) as f32;
+    let confidence = text_confidence
+        * straightness
+        * label_plausibility(micrometer_distance, pixel_distance, width)
+        * pixel_size.map_or(1.0, |pixel_size| agreement(scale, pixel_size.micrometers))
+        * magnified.map_or(1.0, |expected| agreement(scale, expected));
+
+    // Handle an unreliable scale according to the configured policy
+    let confidence = confidence.clamp(0.0, 1.0);
+    if confidence < config.minimum_confidence {
+        match config.low_confidence {
+            ConfidencePolicy::Warn => {}
+            ConfidencePolicy::Reject => return Err(Error::LowScaleConfidence(confidence)),
+            ConfidencePolicy::Override => {
+                if config.override_scale_pixels == 0 {
+                    return Err(Error::LowScaleConfidence(confidence));
+                }
+
+                return Ok((
+                    Scale {
+                        scale: config.override_scale_micrometers
+                            / (config.override_scale_pixels as f32),
+                        micrometers: config.override_scale_micrometers,
+                        pixels: config.override_scale_pixels,
+                        scale_bar_height: height - scale_bar_height,
+                        source: ScaleSource::Override,
+                        confidence,
+                    },
+                    image_without_text,
+                ));
+            }
+        }
+    }
+
     // Return the scale (micrometer / pixel) and the image without the text at the bottom
     Ok((
-        micrometer_distance / (pixel_distance as f32),
-        micrometer_distance,
-        pixel_distance,
-        height - scale_bar_height,
+        Scale {
+            scale,
+            micrometers: micrometer_distance,
+            pixels: pixel_distance,
+            scale_bar_height: height - scale_bar_height,
+            source: ScaleSource::TextRecognition,
+            confidence,
+        },
         image_without_text,
     ))
 }
+
+/// How plausible a scale bar label is from 0 to 1. Labels are round numbers with at most two
+/// significant digits (such as 1, 2.5 or 200) and bars cover a reasonable part of the width
+fn label_plausibility(micrometers: f32, pixels: u32, width: u32) -> f32 {
+    let magnitude = 10_f32.powf(micrometers.log10().floor() - 1.0);
+    let significant = micrometers / magnitude;
+    let round = if (significant - significant.round()).abs() < 0.01 {
+        1.0
+    } else {
+        0.5
+    };
+
+    let coverage = pixels as f32 / width as f32;
+    let length = if (0.02..=0.8).contains(&coverage) {
+        1.0
+    } else {
+        0.5
+    };
+
+    round * length
+}
+
+/// How well two pixel sizes agree from 0 to 1, which is 0 when they differ by 20 % or more
+fn agreement(scale: f32, expected: f32) -> f32 {
+    (1.0 - (scale / expected).ln().abs() / 1.2_f32.ln()).max(0.0)
+}
+
+/// The units found on scale bar labels
+const LABEL_UNITS: [&str; 5] = ["nm", "um", "µm", "μm", "mm"];
+
+/// Parses the text of a scale bar label such as "200 nm", "2.5 µm" or "1 mm" and returns the
+/// length in micrometers. Whitespace is ignored as the text recognition sometimes splits numbers
+fn parse_label(text: &str) -> Option<f32> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let (digits, unit) = LABEL_UNITS
+        .iter()
+        .find_map(|unit| Some((text.strip_suffix(unit)?, *unit)))?;
+
+    // Only plain decimal numbers are accepted, not signs or exponents
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+
+    let distance = digits.parse::<f32>().ok()? * micrometers_per_unit(unit)?;
+
+    Some(distance).filter(|distance| 0.0 < *distance)
+}
+
+/// Recognizes the text in the image using command line Tesseract (to be platform agnostic) and
+/// returns it together with the confidence of the least confident word
+fn run_tesseract(image: &GrayImage) -> Result<(String, f32), Error> {
+    let image = filter::gaussian_blur_f32(image, 1.0);
+
+    // A bit of a hack but here we export the image to a temporary file so that Tesseract then
+    // easily can analyse it
+    let scale_path = env::temp_dir().join(format!(
+        "graphene-analysis-scale-{}-{}.png",
+        process::id(),
+        TEMPORARY_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    image.save(&scale_path).unwrap();
+
+    let output = Command::new("tesseract")
+        .arg(&scale_path)
+        .args([
+            "stdout",
+            "-l",
+            "eng",
+            "--psm",
+            "7",
+            "-c",
+            "tessedit_char_whitelist=\"1234567890.numµ\"",
+            "tsv",
+        ])
+        .output();
+
+    // The file is only used by Tesseract so failing to remove it is not a problem
+    let _ = fs::remove_file(&scale_path);
+
+    let output = output.map_err(|e| Error::TesseractUnavailable(e.to_string()))?;
+
+    Ok(parse_tesseract_tsv(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Parses the TSV output of Tesseract into the recognized words separated by spaces and the
+/// lowest word confidence (from 0 to 1)
+fn parse_tesseract_tsv(tsv: &str) -> (String, f32) {
+    let mut words = Vec::new();
+    let mut confidence = 1.0_f32;
+
+    // The columns are level, page, block, paragraph, line, word, left, top, width, height,
+    // confidence and text, where words are on level 5
+    for line in tsv.lines().skip(1) {
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 12 || columns[0] != "5" || columns[11].trim().is_empty() {
+            continue;
+        }
+
+        words.push(columns[11].trim());
+        confidence = confidence.min(columns[10].parse::<f32>().unwrap_or(0.0) / 100.0);
+    }
+
+    if words.is_empty() {
+        confidence = 0.0;
+    }
+
+    (words.join(" "), confidence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_label;
+
+    #[test]
+    fn parses_nanometers() {
+        assert_eq!(parse_label("200 nm"), Some(0.2));
+        assert_eq!(parse_label("500nm"), Some(0.5));
+    }
+
+    #[test]
+    fn parses_micrometers() {
+        assert_eq!(parse_label("10 um"), Some(10.0));
+        assert_eq!(parse_label("2.5 µm"), Some(2.5));
+        assert_eq!(parse_label("20 μm\n"), Some(20.0));
+    }
+
+    #[test]
+    fn parses_millimeters() {
+        assert_eq!(parse_label("1 mm"), Some(1000.0));
+        assert_eq!(parse_label("0.5mm"), Some(500.0));
+    }
+
+    #[test]
+    fn ignores_whitespace_within_the_number() {
+        assert_eq!(parse_label(" 1 00 nm "), Some(0.1));
+    }
+
+    #[test]
+    fn rejects_invalid_labels() {
+        assert_eq!(parse_label("10"), None);
+        assert_eq!(parse_label("um"), None);
+        assert_eq!(parse_label("10 m"), None);
+        assert_eq!(parse_label("1e3 nm"), None);
+        assert_eq!(parse_label("0 um"), None);
+        assert_eq!(parse_label("1.2.3 um"), None);
+    }
+}
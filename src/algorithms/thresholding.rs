@@ -0,0 +1,197 @@
+use image::{imageops, GrayImage, Luma};
+use imageproc::contrast::otsu_level;
+
+use crate::{
+    artifacts::{Artifact, ArtifactError, ArtifactSink, Table},
+    configuration::ThresholdSelection,
+};
+
+/// Thresholds the contrast of an image with a threshold chosen for the image itself. Returns
+/// (edges, threshold), where the threshold of tiled selection is the mean of the tiles
+pub fn select_threshold(
+    contrast: &GrayImage,
+    selection: ThresholdSelection,
+    fixed_threshold: f32,
+    artifacts: &dyn ArtifactSink,
+) -> Result<(GrayImage, f32), ArtifactError> {
+    let histogram = histogram(contrast.pixels().map(|pixel| pixel.0[0]));
+
+    let threshold = match selection {
+        ThresholdSelection::Fixed => fixed_threshold,
+        ThresholdSelection::Otsu => f32::from(otsu_level(contrast)),
+        ThresholdSelection::Triangle => f32::from(triangle_level(&histogram)),
+        ThresholdSelection::Percentile { percentile } => {
+            f32::from(percentile_level(&histogram, percentile))
+        }
+        ThresholdSelection::Tiled {
+            tile_size,
+            minimum_threshold,
+        } => return tiled_threshold(contrast, tile_size, minimum_threshold, artifacts),
+    };
+
+    let mut edges = contrast.clone();
+    for pixel in edges.pixels_mut() {
+        pixel.0[0] = if threshold < f32::from(pixel.0[0]) {
+            255
+        } else {
+            0
+        };
+    }
+
+    Ok((edges, threshold))
+}
+
+/// The number of pixels with each value
+fn histogram(values: impl Iterator<Item = u8>) -> [u32; 256] {
+    let mut histogram = [0; 256];
+    for value in values {
+        histogram[value as usize] += 1;
+    }
+
+    histogram
+}
+
+/// The level furthest from the line between the peak of the histogram and the end of its longest
+/// tail, which finds where a small population of edges separates from the background
+fn triangle_level(histogram: &[u32; 256]) -> u8 {
+    let Some(first) = histogram.iter().position(|count| 0 < *count) else {
+        return 0;
+    };
+    let last = histogram.iter().rposition(|count| 0 < *count).unwrap();
+
+    let (peak, peak_count) = histogram
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, count)| **count)
+        .map(|(level, count)| (level, f64::from(*count)))
+        .unwrap();
+
+    // The line goes to the furthest end of the histogram from the peak
+    let end = if last - peak >= peak - first {
+        last
+    } else {
+        first
+    };
+    if end == peak {
+        return peak as u8;
+    }
+
+    // The distance to the line from (peak, peak count) to (end, 0) is proportional to this
+    let distance = |level: usize| {
+        let along = (level as f64 - peak as f64) / (end as f64 - peak as f64);
+        peak_count * (1.0 - along) - f64::from(histogram[level])
+    };
+
+    let levels: Vec<usize> = if peak < end {
+        (peak..=end).collect()
+    } else {
+        (end..=peak).collect()
+    };
+
+    levels
+        .into_iter()
+        .max_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+        .unwrap_or(peak) as u8
+}
+
+/// The lowest level at or above the given percentile (0-100) of the values
+fn percentile_level(histogram: &[u32; 256], percentile: f32) -> u8 {
+    let total: u32 = histogram.iter().sum();
+    let target = (f64::from(total) * f64::from(percentile.clamp(0.0, 100.0)) / 100.0).ceil();
+
+    let mut cumulative = 0;
+    for (level, count) in histogram.iter().enumerate() {
+        cumulative += count;
+        if target <= f64::from(cumulative) {
+            return level as u8;
+        }
+    }
+
+    u8::MAX
+}
+
+/// Thresholds every tile by Otsu's method on its own, but never below the minimum. Returns
+/// (edges, mean threshold of the tiles)
+fn tiled_threshold(
+    contrast: &GrayImage,
+    tile_size: u32,
+    minimum_threshold: f32,
+    artifacts: &dyn ArtifactSink,
+) -> Result<(GrayImage, f32), ArtifactError> {
+    let tile_size = tile_size.max(1);
+    let mut edges = GrayImage::new(contrast.width(), contrast.height());
+    let mut thresholds = Vec::new();
+
+    for top in (0..contrast.height()).step_by(tile_size as usize) {
+        for left in (0..contrast.width()).step_by(tile_size as usize) {
+            let width = tile_size.min(contrast.width() - left);
+            let height = tile_size.min(contrast.height() - top);
+
+            let tile = imageops::crop_imm(contrast, left, top, width, height).to_image();
+            let threshold = f32::from(otsu_level(&tile)).max(minimum_threshold);
+
+            for (x, y, pixel) in tile.enumerate_pixels() {
+                if threshold < f32::from(pixel.0[0]) {
+                    edges.put_pixel(left + x, top + y, Luma([255]));
+                }
+            }
+
+            thresholds.push((left, top, threshold));
+        }
+    }
+
+    if artifacts.accepts("tile_thresholds.csv") {
+        let table = Table {
+            header: vec!["x".to_string(), "y".to_string(), "threshold".to_string()],
+            rows: thresholds
+                .iter()
+                .map(|(x, y, threshold)| vec![x.to_string(), y.to_string(), threshold.to_string()])
+                .collect(),
+        };
+
+        artifacts.emit("tile_thresholds.csv", Artifact::Table(table))?;
+    }
+
+    let mean = thresholds
+        .iter()
+        .map(|(_, _, threshold)| threshold)
+        .sum::<f32>()
+        / thresholds.len().max(1) as f32;
+
+    Ok((edges, mean))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{histogram, percentile_level, triangle_level};
+
+    #[test]
+    fn percentile_of_uniform_values() {
+        let histogram = histogram(0..=99);
+
+        assert_eq!(percentile_level(&histogram, 90.0), 89);
+        assert_eq!(percentile_level(&histogram, 50.0), 49);
+        assert_eq!(percentile_level(&histogram, 0.0), 0);
+    }
+
+    #[test]
+    fn triangle_finds_the_end_of_the_background() {
+        // A background decaying from a peak at 10 and a small population of edges around 200
+        let mut histogram = [0; 256];
+        for (level, count) in histogram.iter_mut().enumerate().take(60).skip(10) {
+            *count = 1000 / (level as u32 - 9);
+        }
+        for count in &mut histogram[195..205] {
+            *count = 5;
+        }
+
+        let level = triangle_level(&histogram);
+        assert!((20..60).contains(&level), "{level}");
+    }
+
+    #[test]
+    fn triangle_of_a_single_value() {
+        assert_eq!(triangle_level(&histogram([7; 10].into_iter())), 7);
+        assert_eq!(triangle_level(&[0; 256]), 0);
+    }
+}
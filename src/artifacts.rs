@@ -0,0 +1,247 @@
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Cursor, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// An intermediate result of one of the stages, exported for insight into the algorithms
+#[derive(Debug, Clone)]
+pub enum Artifact {
+    /// An image such as a mask, a visualization or a plot
+    Image(DynamicImage),
+    /// A table of values, written as CSV
+    Table(Table),
+}
+
+/// A table of values, the header is omitted when empty
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Receives the artifacts created by the algorithms. Artifacts are named by the suffix of the
+/// file they are written to, e.g. `graphene.png` or `angles.csv`
+pub trait ArtifactSink: Sync {
+    /// Whether the artifact should be created at all, allowing the algorithms to skip the work
+    /// of creating artifacts that would be thrown away
+    fn accepts(&self, name: &str) -> bool;
+
+    /// Stores the artifact
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError>;
+}
+
+impl<S: ArtifactSink + ?Sized> ArtifactSink for &S {
+    fn accepts(&self, name: &str) -> bool {
+        (**self).accepts(name)
+    }
+
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError> {
+        (**self).emit(name, artifact)
+    }
+}
+
+/// An error when encoding or storing an artifact
+#[derive(Debug)]
+pub enum ArtifactError {
+    Image(image::ImageError),
+    Csv(csv::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArtifactError::Image(e) => write!(f, "Failed to encode image artifact ({e})"),
+            ArtifactError::Csv(e) => write!(f, "Failed to encode table artifact ({e})"),
+            ArtifactError::Io(e) => write!(f, "Failed to write artifact ({e})"),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl From<image::ImageError> for ArtifactError {
+    fn from(e: image::ImageError) -> Self {
+        ArtifactError::Image(e)
+    }
+}
+
+impl From<csv::Error> for ArtifactError {
+    fn from(e: csv::Error) -> Self {
+        ArtifactError::Csv(e)
+    }
+}
+
+impl From<io::Error> for ArtifactError {
+    fn from(e: io::Error) -> Self {
+        ArtifactError::Io(e)
+    }
+}
+
+impl Artifact {
+    /// Encodes the artifact as PNG (images) or CSV (tables)
+    pub fn encode(&self) -> Result<Vec<u8>, ArtifactError> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    /// Writes the artifact as PNG (images) or CSV (tables)
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), ArtifactError> {
+        match self {
+            Artifact::Image(image) => {
+                let mut bytes = Cursor::new(Vec::new());
+                image.write_to(&mut bytes, ImageOutputFormat::Png)?;
+                writer.write_all(bytes.get_ref())?;
+            }
+            Artifact::Table(table) => {
+                let mut csv = csv::Writer::from_writer(writer);
+
+                if !table.header.is_empty() {
+                    csv.write_record(&table.header)?;
+                }
+
+                for row in &table.rows {
+                    csv.write_record(row)?;
+                }
+
+                csv.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The MIME type of the encoded artifact
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Artifact::Image(_) => ImageFormat::Png.to_mime_type(),
+            Artifact::Table(_) => "text/csv",
+        }
+    }
+}
+
+/// Writes artifacts to files in a directory, prefixing the artifact name with a common prefix
+/// (usually the name of the image)
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    directory: PathBuf,
+    prefix: String,
+}
+
+impl DirectorySink {
+    pub fn new(directory: impl Into<PathBuf>, prefix: impl Into<String>) -> Self {
+        DirectorySink {
+            directory: directory.into(),
+            prefix: prefix.into(),
+        }
+    }
+
+    /// The path an artifact is written to
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.directory.join(self.prefix.clone() + name)
+    }
+}
+
+impl ArtifactSink for DirectorySink {
+    fn accepts(&self, _name: &str) -> bool {
+        true
+    }
+
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError> {
+        let mut file = io::BufWriter::new(fs::File::create(self.path(name))?);
+        artifact.write_to(&mut file)?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Keeps artifacts in memory, e.g. for serving them or inspecting them in tests
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    artifacts: Mutex<BTreeMap<String, Artifact>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    /// The names of all the artifacts received so far
+    pub fn names(&self) -> Vec<String> {
+        self.artifacts.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// A copy of the artifact with the given name
+    pub fn get(&self, name: &str) -> Option<Artifact> {
+        self.artifacts.lock().unwrap().get(name).cloned()
+    }
+
+    /// All the artifacts received, by name
+    pub fn into_artifacts(self) -> BTreeMap<String, Artifact> {
+        self.artifacts.into_inner().unwrap()
+    }
+}
+
+impl ArtifactSink for MemorySink {
+    fn accepts(&self, _name: &str) -> bool {
+        true
+    }
+
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError> {
+        self.artifacts
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), artifact);
+
+        Ok(())
+    }
+}
+
+/// Discards all artifacts, the algorithms won't even create them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoSink;
+
+impl ArtifactSink for NoSink {
+    fn accepts(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn emit(&self, _name: &str, _artifact: Artifact) -> Result<(), ArtifactError> {
+        Ok(())
+    }
+}
+
+/// Only passes on the artifacts with the given names to the inner sink
+#[derive(Debug, Clone)]
+pub struct Filter<S> {
+    sink: S,
+    names: Vec<String>,
+}
+
+impl<S: ArtifactSink> Filter<S> {
+    pub fn new(sink: S, names: Vec<String>) -> Self {
+        Filter { sink, names }
+    }
+}
+
+impl<S: ArtifactSink> ArtifactSink for Filter<S> {
+    fn accepts(&self, name: &str) -> bool {
+        self.names.iter().any(|kept| kept == name) && self.sink.accepts(name)
+    }
+
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError> {
+        if self.accepts(name) {
+            self.sink.emit(name, artifact)
+        } else {
+            Ok(())
+        }
+    }
+}
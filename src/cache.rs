@@ -0,0 +1,272 @@
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write as _},
+    fs,
+    path::PathBuf,
+    process,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    algorithms::Scale,
+    artifacts::{Artifact, ArtifactError, ArtifactSink, Table},
+};
+
+/// The output of a stage that is passed on to the later ones
+#[derive(Debug, Clone)]
+pub struct StageOutput {
+    /// The image passed on to the next stage
+    pub image: GrayImage,
+    /// The scale, if the stage determined it
+    pub scale: Option<Scale>,
+    /// The contrast threshold, if the stage chose it
+    pub contrast_threshold: Option<f32>,
+}
+
+impl StageOutput {
+    /// The output of a stage only producing an image
+    pub fn new(image: GrayImage) -> Self {
+        StageOutput {
+            image,
+            scale: None,
+            contrast_threshold: None,
+        }
+    }
+}
+
+/// The stored output of a stage together with the artifacts it created
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub output: StageOutput,
+    /// The names of all the artifacts the stage asked about, whether or not they were created
+    pub requested: BTreeSet<String>,
+    /// The artifacts the stage created
+    pub artifacts: BTreeMap<String, Artifact>,
+}
+
+impl CacheEntry {
+    /// Whether the entry has every artifact of the stage the sink wants, as a stage run with a
+    /// sink not wanting some artifacts doesn't create them
+    pub fn satisfies(&self, artifacts: &dyn ArtifactSink) -> bool {
+        self.requested
+            .iter()
+            .all(|name| !artifacts.accepts(name) || self.artifacts.contains_key(name))
+    }
+}
+
+/// Stores the outputs of stages by a key derived from everything the output depends on, so
+/// stages are only run again when their input or configuration changes
+pub trait StageCache: Sync + fmt::Debug {
+    /// The entry stored under the key, if any
+    fn load(&self, key: &str) -> Option<CacheEntry>;
+
+    /// Stores the entry, failures are ignored as the stage can always be run again
+    fn store(&self, key: &str, entry: &CacheEntry);
+}
+
+/// Derives a cache key from the parts identifying the output of a stage, as the hex encoded
+/// SHA-256 of the parts
+pub fn key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // Prefix each part by its length so different splits give different keys
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut key, byte| {
+            write!(key, "{byte:02x}").unwrap();
+            key
+        })
+}
+
+/// The metadata of an entry stored on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    scale: Option<Scale>,
+    contrast_threshold: Option<f32>,
+    requested: BTreeSet<String>,
+    /// The names of the image artifacts, the others are tables
+    images: BTreeSet<String>,
+    tables: BTreeSet<String>,
+}
+
+/// Stores entries as directories of PNG and CSV files, which can be shared between runs and
+/// processes
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    directory: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        DiskCache {
+            directory: directory.into(),
+        }
+    }
+
+    fn try_load(&self, key: &str) -> Option<CacheEntry> {
+        let directory = self.directory.join(key);
+
+        // The metadata is read first as an entry without it is incomplete
+        let metadata: Metadata =
+            serde_json::from_slice(&fs::read(directory.join("entry.json")).ok()?).ok()?;
+        let image = image::open(directory.join("image.png")).ok()?.into_luma8();
+
+        let mut artifacts = BTreeMap::new();
+        for name in &metadata.images {
+            let image = image::open(directory.join("artifacts").join(name)).ok()?;
+            artifacts.insert(name.clone(), Artifact::Image(image));
+        }
+
+        for name in &metadata.tables {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_path(directory.join("artifacts").join(name))
+                .ok()?;
+            let mut rows = reader
+                .records()
+                .map(|record| record.map(|record| record.iter().map(String::from).collect()))
+                .collect::<Result<Vec<Vec<String>>, _>>()
+                .ok()?;
+
+            // Tables are always written with their header, which may be empty
+            let header = if rows.is_empty() {
+                Vec::new()
+            } else {
+                rows.remove(0)
+            };
+            artifacts.insert(name.clone(), Artifact::Table(Table { header, rows }));
+        }
+
+        Some(CacheEntry {
+            output: StageOutput {
+                image,
+                scale: metadata.scale,
+                contrast_threshold: metadata.contrast_threshold,
+            },
+            requested: metadata.requested,
+            artifacts,
+        })
+    }
+
+    fn try_store(&self, key: &str, entry: &CacheEntry) -> Result<(), ArtifactError> {
+        // Write to a temporary directory that is renamed once complete, so concurrent runs
+        // never see a partial entry
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        let temporary = self
+            .directory
+            .join(format!("{key}.{}-{nanos}.tmp", process::id()));
+        fs::create_dir_all(temporary.join("artifacts"))?;
+
+        entry.output.image.save(temporary.join("image.png"))?;
+
+        let mut metadata = Metadata {
+            scale: entry.output.scale,
+            contrast_threshold: entry.output.contrast_threshold,
+            requested: entry.requested.clone(),
+            images: BTreeSet::new(),
+            tables: BTreeSet::new(),
+        };
+
+        for (name, artifact) in &entry.artifacts {
+            let path = temporary.join("artifacts").join(name);
+            match artifact {
+                Artifact::Image(_) => {
+                    fs::write(path, artifact.encode()?)?;
+                    metadata.images.insert(name.clone());
+                }
+                Artifact::Table(table) => {
+                    // Always write the header so it can be told apart from the rows
+                    let mut writer = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+                    writer.write_record(&table.header)?;
+                    for row in &table.rows {
+                        writer.write_record(row)?;
+                    }
+                    writer.flush()?;
+                    metadata.tables.insert(name.clone());
+                }
+            }
+        }
+
+        fs::write(
+            temporary.join("entry.json"),
+            serde_json::to_vec(&metadata).expect("Failed to serialize the cache entry"),
+        )?;
+
+        // Replace an outdated entry, such as one missing artifacts
+        let directory = self.directory.join(key);
+        let _ = fs::remove_dir_all(&directory);
+        if fs::rename(&temporary, &directory).is_err() {
+            fs::remove_dir_all(&temporary)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StageCache for DiskCache {
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        self.try_load(key)
+    }
+
+    fn store(&self, key: &str, entry: &CacheEntry) {
+        if let Err(e) = self.try_store(key, entry) {
+            eprintln!("Warning: couldn't write to the cache ({e})");
+        }
+    }
+}
+
+/// Passes artifacts on to another sink while keeping a copy of all of them, and the names of
+/// all the artifacts asked about, to store them with the output of a stage
+pub(crate) struct Recorder<'a> {
+    sink: &'a dyn ArtifactSink,
+    requested: Mutex<BTreeSet<String>>,
+    artifacts: Mutex<BTreeMap<String, Artifact>>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(sink: &'a dyn ArtifactSink) -> Self {
+        Recorder {
+            sink,
+            requested: Mutex::new(BTreeSet::new()),
+            artifacts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The entry of the output of the stage with the recorded artifacts
+    pub fn into_entry(self, output: StageOutput) -> CacheEntry {
+        CacheEntry {
+            output,
+            requested: self.requested.into_inner().unwrap(),
+            artifacts: self.artifacts.into_inner().unwrap(),
+        }
+    }
+}
+
+impl ArtifactSink for Recorder<'_> {
+    fn accepts(&self, name: &str) -> bool {
+        self.requested.lock().unwrap().insert(name.to_string());
+        self.sink.accepts(name)
+    }
+
+    fn emit(&self, name: &str, artifact: Artifact) -> Result<(), ArtifactError> {
+        self.requested.lock().unwrap().insert(name.to_string());
+        self.artifacts
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), artifact.clone());
+
+        self.sink.emit(name, artifact)
+    }
+}
@@ -0,0 +1,333 @@
+use graphene_analysis::{
+    algorithms::{
+        determine_scale, exclusion_zone, graphene_angles, graphene_edges, pre_processing, Edges,
+        Flake,
+    },
+    artifacts::NoSink,
+    Configuration, Input,
+};
+use image::GrayImage;
+use plotters::prelude::{
+    BitMapBackend, ChartBuilder, Circle, Color, IntoDrawingArea, LineSeries, BLACK, RED, WHITE,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::statistics;
+
+/// The size of the scatter plot in pixels
+const PLOT_WIDTH: u32 = 640;
+const PLOT_HEIGHT: u32 = 480;
+
+/// The values a parameter takes in a sweep, either a list (`0.5,0.9,1.2`) or an inclusive range
+/// with a step (`0.5:2.0:0.1`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Values(pub Vec<f32>);
+
+impl FromStr for Values {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let parse = |number: &str| {
+            number
+                .trim()
+                .parse::<f32>()
+                .map_err(|e| format!("{number:?} is not a number ({e})"))
+        };
+
+        if text.contains(':') {
+            let parts = text.split(':').map(parse).collect::<Result<Vec<_>, _>>()?;
+            let [start, end, step] = parts[..] else {
+                return Err("A range has to be given as start:end:step".to_string());
+            };
+
+            if step <= 0.0 || end < start {
+                return Err("A range needs a positive step and an end after the start".to_string());
+            }
+
+            // Allow for rounding errors in the step so the end is included
+            let count = ((end - start) / step + 1e-4).floor() as usize + 1;
+            Ok(Values(
+                (0..count).map(|i| start + i as f32 * step).collect(),
+            ))
+        } else {
+            Ok(Values(
+                text.split(',').map(parse).collect::<Result<_, _>>()?,
+            ))
+        }
+    }
+}
+
+/// The correlation maximised by the calibration
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum Objective {
+    #[default]
+    Pearson,
+    Spearman,
+}
+
+/// An image with an experimentally measured bacterial viability or coverage
+#[derive(Debug, Clone, Deserialize)]
+pub struct Measurement {
+    /// The path of the image, relative to the measurement file
+    pub image: PathBuf,
+    pub measurement: f64,
+}
+
+/// Reads the measurements from a CSV file with `image` and `measurement` columns
+pub fn read_measurements(path: &Path) -> csv::Result<Vec<Measurement>> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+
+    csv::Reader::from_path(path)?
+        .deserialize()
+        .map(|measurement| {
+            measurement.map(|measurement: Measurement| Measurement {
+                image: directory.join(measurement.image),
+                ..measurement
+            })
+        })
+        .collect()
+}
+
+/// An image with its scale determined and pre-processed, ready for the exclusion stage
+struct Prepared {
+    image: GrayImage,
+    scale: f32,
+    /// The flakes of the image, if the exclusion mode uses them
+    flakes: Vec<Flake>,
+    measurement: f64,
+}
+
+/// How well the predicted exclusion of a set of parameters correlates with the measurements
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub exclusion_radius: f32,
+    pub contrast_threshold: f32,
+    /// The number of images the exclusion could be calculated for
+    pub count: usize,
+    pub pearson: Option<f64>,
+    pub spearman: Option<f64>,
+    /// The coefficient of determination of a linear fit of the measurements to the exclusion
+    pub r_squared: Option<f64>,
+    pub slope: Option<f64>,
+    pub intercept: Option<f64>,
+    /// The exclusion ratio and measurement of every image
+    #[serde(skip)]
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Candidate {
+    /// The value of the objective, the absolute correlation since the measurements can both
+    /// increase (coverage) or decrease (viability) with the exclusion
+    pub fn score(&self, objective: Objective) -> Option<f64> {
+        match objective {
+            Objective::Pearson => self.pearson,
+            Objective::Spearman => self.spearman,
+        }
+        .map(f64::abs)
+    }
+}
+
+/// Calculates the exclusion ratio of every measured image for every combination of the radii
+/// and thresholds, and how well it correlates with the measurements. The scale and
+/// pre-processing are only done once per image
+pub fn calibrate(
+    config: &Configuration,
+    measurements: &[Measurement],
+    radii: &Values,
+    thresholds: &Values,
+) -> Vec<Candidate> {
+    let prepared: Vec<Prepared> = measurements
+        .par_iter()
+        .filter_map(|measurement| {
+            let prepared = Input::open(&measurement.image)
+                .map_err(|e| e.to_string())
+                .and_then(|input| {
+                    determine_scale(
+                        input.image,
+                        &config.text_recognition,
+                        input.pixel_size,
+                        input.magnification,
+                        &NoSink,
+                    )
+                    .map_err(|e| e.to_string())
+                })
+                .and_then(|(scale, image)| {
+                    let image = pre_processing(image, config.pre_processing);
+                    let flakes = if config.bacteria_exclusion.exclusion_mode.uses_flakes() {
+                        graphene_angles(&image, &config.graphene_angles, scale.scale, &NoSink)
+                            .map_err(|e| e.to_string())?
+                    } else {
+                        Vec::new()
+                    };
+
+                    Ok(Prepared {
+                        image,
+                        scale: scale.scale,
+                        flakes,
+                        measurement: measurement.measurement,
+                    })
+                });
+
+            match prepared {
+                Ok(prepared) => Some(prepared),
+                Err(e) => {
+                    eprintln!("Warning: skipping {} ({e})", measurement.image.display());
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // The edges only depend on the threshold, so they are found once per threshold and image
+    // and reused for every radius. Images where they can't be found are left out
+    let edges: Vec<Vec<Option<Edges>>> = thresholds
+        .0
+        .par_iter()
+        .map(|&contrast_threshold| {
+            let mut parameters = config.bacteria_exclusion;
+            parameters.contrast_threshold = contrast_threshold;
+
+            prepared
+                .par_iter()
+                .map(|image| graphene_edges(&image.image, &parameters, &NoSink).ok())
+                .collect()
+        })
+        .collect();
+
+    let combinations: Vec<(f32, usize)> = radii
+        .0
+        .iter()
+        .flat_map(|radius| (0..thresholds.0.len()).map(move |threshold| (*radius, threshold)))
+        .collect();
+
+    combinations
+        .par_iter()
+        .map(|&(exclusion_radius, threshold)| {
+            let contrast_threshold = thresholds.0[threshold];
+            let mut parameters = config.bacteria_exclusion;
+            parameters.exclusion_radius = exclusion_radius;
+            parameters.contrast_threshold = contrast_threshold;
+
+            // Images where the exclusion can't be calculated (such as a radius below a pixel)
+            // are left out
+            let points: Vec<(f64, f64)> = prepared
+                .iter()
+                .zip(&edges[threshold])
+                .filter_map(|(image, edges)| {
+                    exclusion_zone(
+                        &image.image,
+                        edges.as_ref()?,
+                        &image.flakes,
+                        &parameters,
+                        image.scale,
+                        &NoSink,
+                    )
+                    .ok()
+                    .map(|exclusion| (f64::from(exclusion.value()), image.measurement))
+                })
+                .collect();
+
+            let (exclusion, measured): (Vec<f64>, Vec<f64>) = points.iter().copied().unzip();
+            let pearson = statistics::pearson(&exclusion, &measured);
+            let fit = statistics::linear_fit(&exclusion, &measured);
+
+            Candidate {
+                exclusion_radius,
+                contrast_threshold,
+                count: points.len(),
+                pearson,
+                spearman: statistics::spearman(&exclusion, &measured),
+                r_squared: pearson.map(|r| r * r),
+                slope: fit.map(|(slope, _)| slope),
+                intercept: fit.map(|(_, intercept)| intercept),
+                points,
+            }
+        })
+        .collect()
+}
+
+/// Writes the correlation of every candidate as CSV
+pub fn write(path: &Path, candidates: &[Candidate]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    for candidate in candidates {
+        writer.serialize(candidate)?;
+    }
+
+    writer.flush()
+}
+
+/// Draws the measurements against the predicted exclusion of a candidate with its linear fit
+pub fn plot(path: &Path, candidate: &Candidate) {
+    let mut x_range = (f64::INFINITY, f64::NEG_INFINITY);
+    let mut y_range = (f64::INFINITY, f64::NEG_INFINITY);
+    for (x, y) in &candidate.points {
+        x_range = (x_range.0.min(*x), x_range.1.max(*x));
+        y_range = (y_range.0.min(*y), y_range.1.max(*y));
+    }
+
+    // Leave some room around the points
+    let x_margin = ((x_range.1 - x_range.0) * 0.1).max(1e-3);
+    let y_margin = ((y_range.1 - y_range.0) * 0.1).max(1e-3);
+    let x_range = x_range.0 - x_margin..x_range.1 + x_margin;
+    let y_range = y_range.0 - y_margin..y_range.1 + y_margin;
+
+    let canvas = BitMapBackend::new(path, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area();
+    canvas.fill(&WHITE).unwrap();
+
+    // Create a chart with a caption
+    let mut chart = ChartBuilder::on(&canvas)
+        .x_label_area_size(35)
+        .y_label_area_size(50)
+        .caption(
+            format!(
+                "Radius {} μm, threshold {} (R² = {:.3})",
+                candidate.exclusion_radius,
+                candidate.contrast_threshold,
+                candidate.r_squared.unwrap_or(0.0)
+            ),
+            ("sans-serif", 24),
+        )
+        .margin(15)
+        .build_cartesian_2d(x_range.clone(), y_range)
+        .unwrap();
+
+    // Add X and Y labels to the chart
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .bold_line_style(WHITE.mix(0.3))
+        .x_desc("Predicted exclusion ratio")
+        .y_desc("Measurement")
+        .axis_desc_style(("sans-serif", 15))
+        .draw()
+        .unwrap();
+
+    chart
+        .draw_series(
+            candidate
+                .points
+                .iter()
+                .map(|point| Circle::new(*point, 5, BLACK.filled())),
+        )
+        .unwrap();
+
+    if let (Some(slope), Some(intercept)) = (candidate.slope, candidate.intercept) {
+        chart
+            .draw_series(LineSeries::new(
+                [x_range.start, x_range.end].map(|x| (x, slope * x + intercept)),
+                RED.stroke_width(2),
+            ))
+            .unwrap();
+    }
+
+    canvas.present().expect("Failed to draw plot");
+}
@@ -0,0 +1,298 @@
+use plotters::prelude::{
+    BitMapBackend, Boxplot, ChartBuilder, Circle, Color, IntoDrawingArea, IntoSegmentedCoord,
+    Quartiles, SegmentValue, BLACK, WHITE,
+};
+use serde::Serialize;
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::{
+    groups::{Metric, AXIAL_METRICS, METRICS},
+    statistics::{self, TestResult},
+    summary::{Status, SummaryRow},
+};
+
+/// The size of the box plots in pixels
+const PLOT_WIDTH: u32 = 640;
+const PLOT_HEIGHT: u32 = 480;
+
+/// The outcome of a statistical test of a metric between groups
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub metric: &'static str,
+    pub test: &'static str,
+    /// The groups of a pairwise test, empty for tests of all groups
+    pub group_a: Option<String>,
+    pub group_b: Option<String>,
+    pub statistic: f64,
+    pub df1: Option<f64>,
+    pub df2: Option<f64>,
+    pub p_value: f64,
+    pub effect_size: f64,
+    /// What the effect size measures
+    pub effect_measure: &'static str,
+}
+
+impl Comparison {
+    fn new(
+        metric: &'static str,
+        test: &'static str,
+        groups: Option<(&str, &str)>,
+        result: TestResult,
+        effect_measure: &'static str,
+    ) -> Self {
+        Comparison {
+            metric,
+            test,
+            group_a: groups.map(|(a, _)| a.to_string()),
+            group_b: groups.map(|(_, b)| b.to_string()),
+            statistic: result.statistic,
+            df1: result.degrees_of_freedom.0,
+            df2: result.degrees_of_freedom.1,
+            p_value: result.p_value,
+            effect_size: result.effect_size,
+            effect_measure,
+        }
+    }
+}
+
+/// The values of a metric in every group (in alphabetical order) from the successfully analysed
+/// images, leaving out groups without any values
+pub fn group_values(rows: &[SummaryRow], metric: Metric) -> BTreeMap<String, Vec<f64>> {
+    let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for row in rows {
+        if row.status != Status::Ok {
+            continue;
+        }
+
+        if let (Some(group), Some(value)) = (&row.group, metric(row)) {
+            groups.entry(group.clone()).or_default().push(value);
+        }
+    }
+
+    groups
+}
+
+/// Compares every metric between the groups, with Welch's t-test and the Mann-Whitney U test
+/// between each pair of groups and a one-way ANOVA with Tukey's post-hoc test across all of them.
+/// The axial metrics (the mean angle) are compared with the Watson-Williams test of the doubled
+/// angles instead, between each pair and across all groups
+pub fn compare(rows: &[SummaryRow]) -> Vec<Comparison> {
+    let mut comparisons = Vec::new();
+
+    for (metric, value) in METRICS {
+        let groups = group_values(rows, value);
+        let names: Vec<&str> = groups.keys().map(String::as_str).collect();
+        let samples: Vec<&[f64]> = groups.values().map(Vec::as_slice).collect();
+
+        for i in 0..samples.len() {
+            for j in i + 1..samples.len() {
+                let pair = Some((names[i], names[j]));
+
+                if let Some(result) = statistics::welch_t_test(samples[i], samples[j]) {
+                    comparisons.push(Comparison::new(metric, "welch_t", pair, result, "cohens_d"));
+                }
+
+                if let Some(result) = statistics::mann_whitney_u(samples[i], samples[j]) {
+                    comparisons.push(Comparison::new(
+                        metric,
+                        "mann_whitney_u",
+                        pair,
+                        result,
+                        "rank_biserial",
+                    ));
+                }
+            }
+        }
+
+        if let Some(result) = statistics::one_way_anova(&samples) {
+            comparisons.push(Comparison::new(
+                metric,
+                "anova",
+                None,
+                result,
+                "eta_squared",
+            ));
+
+            for (i, j, result) in statistics::tukey_hsd(&samples) {
+                comparisons.push(Comparison::new(
+                    metric,
+                    "tukey_hsd",
+                    Some((names[i], names[j])),
+                    result,
+                    "standardized_difference",
+                ));
+            }
+        }
+    }
+
+    for (metric, value) in AXIAL_METRICS {
+        // Doubling the axes makes opposite directions the same angle
+        let groups: BTreeMap<String, Vec<f64>> = group_values(rows, value)
+            .into_iter()
+            .map(|(group, angles)| (group, angles.iter().map(|angle| 2.0 * angle).collect()))
+            .collect();
+        let names: Vec<&str> = groups.keys().map(String::as_str).collect();
+        let samples: Vec<&[f64]> = groups.values().map(Vec::as_slice).collect();
+
+        for i in 0..samples.len() {
+            for j in i + 1..samples.len() {
+                if let Some(result) = statistics::watson_williams(&[samples[i], samples[j]]) {
+                    comparisons.push(Comparison::new(
+                        metric,
+                        "watson_williams",
+                        Some((names[i], names[j])),
+                        result,
+                        "circular_eta_squared",
+                    ));
+                }
+            }
+        }
+
+        if let Some(result) = statistics::watson_williams(&samples) {
+            comparisons.push(Comparison::new(
+                metric,
+                "watson_williams",
+                None,
+                result,
+                "circular_eta_squared",
+            ));
+        }
+    }
+
+    comparisons
+}
+
+/// Writes the comparisons as CSV with one row per test
+pub fn write(path: &Path, comparisons: &[Comparison]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    for comparison in comparisons {
+        writer.serialize(comparison)?;
+    }
+
+    writer.flush()
+}
+
+/// Draws a box plot of the values of a metric in each group, with the individual images on top
+pub fn plot(path: &Path, metric: &str, groups: &BTreeMap<String, Vec<f64>>) {
+    let names: Vec<&str> = groups.keys().map(String::as_str).collect();
+
+    let mut minimum = f64::INFINITY;
+    let mut maximum = f64::NEG_INFINITY;
+    for value in groups.values().flatten() {
+        minimum = minimum.min(*value);
+        maximum = maximum.max(*value);
+    }
+
+    // Leave some room above and below the values
+    let margin = ((maximum - minimum) * 0.1).max(1e-3);
+    let range = (minimum - margin) as f32..(maximum + margin) as f32;
+
+    let canvas = BitMapBackend::new(path, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area();
+    canvas.fill(&WHITE).unwrap();
+
+    // Create a chart with a caption
+    let mut chart = ChartBuilder::on(&canvas)
+        .x_label_area_size(35)
+        .y_label_area_size(50)
+        .caption(format!("{metric} by group"), ("sans-serif", 30))
+        .margin(15)
+        .build_cartesian_2d(names[..].into_segmented(), range)
+        .unwrap();
+
+    // Add X and Y labels to the chart
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .bold_line_style(WHITE.mix(0.3))
+        .x_label_formatter(&|segment| match segment {
+            SegmentValue::Exact(name) | SegmentValue::CenterOf(name) => (*name).to_string(),
+            SegmentValue::Last => String::new(),
+        })
+        .x_desc("Group")
+        .y_desc(metric)
+        .axis_desc_style(("sans-serif", 15))
+        .draw()
+        .unwrap();
+
+    chart
+        .draw_series(names.iter().zip(groups.values()).map(|(name, values)| {
+            Boxplot::new_vertical(SegmentValue::CenterOf(name), &Quartiles::new(values)).width(30)
+        }))
+        .unwrap();
+
+    chart
+        .draw_series(
+            names
+                .iter()
+                .zip(groups.values())
+                .flat_map(|(name, values)| {
+                    values.iter().map(move |value| {
+                        Circle::new(
+                            (SegmentValue::CenterOf(name), *value as f32),
+                            3,
+                            BLACK.filled(),
+                        )
+                    })
+                }),
+        )
+        .unwrap();
+
+    canvas.present().expect("Failed to draw plot");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::PI, path::Path, time::Duration};
+
+    use super::compare;
+    use crate::summary::{Status, SummaryRow};
+
+    #[test]
+    fn angles_are_compared_as_axes() {
+        // The angles of group b are the axes of a flipped by π, the same axes, while group c is
+        // perpendicular to them
+        let rows: Vec<SummaryRow> = [
+            ("a", 0.1, 1.5),
+            ("a", 0.2, -1.5),
+            ("a", 0.2, 1.4),
+            ("b", 0.3, 1.5 - PI),
+            ("b", 0.5, PI - 1.5),
+            ("b", 0.4, 1.45 - PI),
+            ("c", 0.3, 0.1),
+            ("c", 0.5, -0.1),
+            ("c", 0.4, 0.05),
+        ]
+        .iter()
+        .map(|(group, ratio, angle)| SummaryRow {
+            group: Some(group.to_string()),
+            status: Status::Ok,
+            error: None,
+            exclusion_ratio: Some(*ratio),
+            mean_angle: Some(*angle),
+            ..SummaryRow::failed(Path::new("image.tif"), String::new(), Duration::ZERO)
+        })
+        .collect();
+
+        let comparisons = compare(&rows);
+        let angles = |a: &str, b: &str| {
+            comparisons
+                .iter()
+                .find(|comparison| {
+                    comparison.metric == "mean_angle"
+                        && comparison.group_a.as_deref() == Some(a)
+                        && comparison.group_b.as_deref() == Some(b)
+                })
+                .unwrap()
+        };
+
+        assert!(comparisons
+            .iter()
+            .all(|comparison| comparison.metric != "mean_angle"
+                || comparison.test == "watson_williams"));
+        assert!(angles("a", "b").p_value > 0.5);
+        assert!(angles("a", "c").p_value < 1e-3);
+    }
+}
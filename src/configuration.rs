@@ -1,26 +1,516 @@
 use git_version::git_version;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::{collections::BTreeMap, fmt::Write};
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct PreProcessing {
     pub equalize_histogram: bool,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+/// The engine used to recognize the text of the scale bar label
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrEngine {
+    /// The external Tesseract binary
+    #[default]
+    Tesseract,
+    /// Template matching against the glyphs of common footer fonts
+    Builtin,
+}
+
+/// The side of the footer the scale bar is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FooterSide {
+    Left,
+    Right,
+}
+
+/// Whether what is searched for, such as the scale bar and its label or the bacteria, is lighter
+/// or darker than its background
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    /// A light bar on a dark footer, or light bacteria on a dark surface
+    Light,
+    /// A dark bar on a light footer, or dark bacteria on a light surface
+    Dark,
+}
+
+/// Where the label is placed relative to the scale bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextPosition {
+    /// In a gap in the middle of the bar, which is then drawn as two lines
+    Between,
+    /// Above or below the bar (given by the offset), spanning its entire length
+    Outside,
+}
+
+/// The layout of the footer with the scale bar, which differs between microscopes
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct FooterProfile {
+    /// The number of pixels at the bottom of the image skipped when looking for the top of the
+    /// footer, to skip past any text in it
+    pub skip_bottom_pixels: u32,
+    /// The side of the footer to look for the scale bar in
+    pub side: FooterSide,
+    /// The width of the region at that side to look for the scale bar in (px)
+    pub region_width: u32,
+    /// The brightness separating the bar and label from the footer (after inverting dark bars)
+    pub threshold: u8,
+    pub polarity: Polarity,
+    pub text_position: TextPosition,
+    /// The distance from the bar up to the top of the label (px), negative if below the bar
+    pub text_offset: i32,
+    /// The height of the label (px)
+    pub text_height: u32,
+}
+
+impl FooterProfile {
+    /// The names of the built-in profiles. Only the footer of the images the program was
+    /// developed with is built in, other microscopes need a profile measured on their images
+    pub const PRESETS: [&'static str; 1] = ["default"];
+
+    /// Gets one of the built-in profiles by name
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            // A white bar split around its label in the bottom right of a black footer
+            "default" => Some(FooterProfile {
+                skip_bottom_pixels: 40,
+                side: FooterSide::Right,
+                region_width: 650,
+                threshold: 240,
+                polarity: Polarity::Light,
+                text_position: TextPosition::Between,
+                text_offset: 18,
+                text_height: 45,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a scale detected with a confidence below the minimum
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfidencePolicy {
+    /// Use the scale but add a warning to the results
+    #[default]
+    Warn,
+    /// Fail the analysis of the image
+    Reject,
+    /// Use the override values instead
+    Override,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TextRecognition {
+    #[serde(default = "enabled")]
+    pub use_metadata: bool,
+    #[serde(default)]
+    pub engine: OcrEngine,
+    /// The name of the footer profile, either one defined in `profiles` or a built-in preset
+    #[serde(default = "default_profile")]
+    pub profile: String,
+    /// Footer profiles defined in the configuration, which take precedence over the presets
+    #[serde(default)]
+    pub profiles: BTreeMap<String, FooterProfile>,
+    /// The confidence (0-1) below which a detected scale is handled by `low_confidence`
+    #[serde(default = "default_minimum_confidence")]
+    pub minimum_confidence: f32,
+    #[serde(default)]
+    pub low_confidence: ConfidencePolicy,
+    /// The width (mm) the magnification stored by the microscope refers to, such as the display
+    /// or print width. When given, the confidence includes whether the scale agrees with it
+    #[serde(default)]
+    pub magnification_reference: Option<f32>,
     pub override_scale: bool,
     pub scale_bar_height: u32,
     pub override_scale_micrometers: f32,
     pub override_scale_pixels: u32,
 }
 
+impl TextRecognition {
+    /// The selected footer profile, if it exists
+    pub fn footer_profile(&self) -> Option<FooterProfile> {
+        self.profiles
+            .get(&self.profile)
+            .copied()
+            .or_else(|| FooterProfile::preset(&self.profile))
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_profile() -> String {
+    "default".to_string()
+}
+
+fn default_minimum_confidence() -> f32 {
+    0.5
+}
+
+/// The gradient kernel used by [`EdgeDetector::Gradient`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientOperator {
+    #[default]
+    Sobel,
+    /// More accurate in the diagonal directions than Sobel
+    Scharr,
+}
+
+/// The method used to find the graphene edges, with its parameters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum EdgeDetector {
+    /// The mean absolute difference between the opposite neighbours of each pixel, thresholded
+    /// by `contrast_threshold`
+    #[default]
+    Contrast,
+    /// Canny's detector, following thin edges above the low threshold from the pixels above the
+    /// high one. The thresholds are on the Sobel gradient magnitude (0-1443)
+    Canny {
+        #[serde(default = "default_canny_low_threshold")]
+        low_threshold: f32,
+        #[serde(default = "default_canny_high_threshold")]
+        high_threshold: f32,
+    },
+    /// The magnitude of the gradient after a Gaussian blur, thresholded by Otsu's method unless
+    /// another threshold selection is configured
+    Gradient {
+        #[serde(default)]
+        operator: GradientOperator,
+        /// The standard deviation of the blur (px), 0 to skip it
+        #[serde(default = "default_gradient_blur")]
+        blur: f32,
+    },
+    /// The zero crossings of the scale-normalized Laplacian of Gaussian at several scales, where
+    /// the response changes by more than the threshold
+    LaplacianOfGaussian {
+        /// The standard deviation of the finest scale (px)
+        #[serde(default = "default_minimum_sigma")]
+        minimum_sigma: f32,
+        /// The standard deviation of the coarsest scale (px)
+        #[serde(default = "default_maximum_sigma")]
+        maximum_sigma: f32,
+        /// The number of scales, spaced geometrically between the two
+        #[serde(default = "default_scales")]
+        scales: u32,
+        #[serde(default = "default_laplacian_threshold")]
+        threshold: f32,
+    },
+}
+
+impl EdgeDetector {
+    /// The names of the methods
+    pub const METHODS: [&'static str; 4] =
+        ["contrast", "canny", "gradient", "laplacian_of_gaussian"];
+
+    /// One of the methods by name with its default parameters
+    pub fn method(name: &str) -> Option<Self> {
+        match name {
+            "contrast" => Some(EdgeDetector::Contrast),
+            "canny" => Some(EdgeDetector::Canny {
+                low_threshold: default_canny_low_threshold(),
+                high_threshold: default_canny_high_threshold(),
+            }),
+            "gradient" => Some(EdgeDetector::Gradient {
+                operator: GradientOperator::default(),
+                blur: default_gradient_blur(),
+            }),
+            "laplacian_of_gaussian" => Some(EdgeDetector::LaplacianOfGaussian {
+                minimum_sigma: default_minimum_sigma(),
+                maximum_sigma: default_maximum_sigma(),
+                scales: default_scales(),
+                threshold: default_laplacian_threshold(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The name of the method
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeDetector::Contrast => "contrast",
+            EdgeDetector::Canny { .. } => "canny",
+            EdgeDetector::Gradient { .. } => "gradient",
+            EdgeDetector::LaplacianOfGaussian { .. } => "laplacian_of_gaussian",
+        }
+    }
+}
+
+fn default_canny_low_threshold() -> f32 {
+    50.0
+}
+
+fn default_canny_high_threshold() -> f32 {
+    100.0
+}
+
+fn default_gradient_blur() -> f32 {
+    1.0
+}
+
+fn default_minimum_sigma() -> f32 {
+    1.0
+}
+
+fn default_maximum_sigma() -> f32 {
+    4.0
+}
+
+fn default_scales() -> u32 {
+    3
+}
+
+fn default_laplacian_threshold() -> f32 {
+    4.0
+}
+
+/// How the threshold on the edge strength (the contrast or the gradient magnitude) of the
+/// contrast and gradient edge detectors is chosen for each image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ThresholdSelection {
+    /// The detector's own threshold, the same `contrast_threshold` for every image with the
+    /// contrast detector and Otsu's method on each image with the gradient detector
+    #[default]
+    Fixed,
+    /// Otsu's method on the histogram of the edge strength
+    Otsu,
+    /// The triangle method on the histogram of the edge strength, suited to edges that are a
+    /// small tail of the histogram
+    Triangle,
+    /// A percentile of the edge strength, so the pixels above it are edges
+    Percentile {
+        /// The percentile of the edge strength used as the threshold (0-100)
+        #[serde(default = "default_percentile")]
+        percentile: f32,
+    },
+    /// Otsu's method in each tile of the image on its own, for uneven brightness and contrast
+    Tiled {
+        /// The width and height of the tiles (px)
+        #[serde(default = "default_tile_size")]
+        tile_size: u32,
+        /// The lowest threshold of a tile, so tiles without edges don't turn noise into edges
+        #[serde(default = "default_minimum_threshold")]
+        minimum_threshold: f32,
+    },
+}
+
+impl ThresholdSelection {
+    /// The names of the methods
+    pub const METHODS: [&'static str; 5] = ["fixed", "otsu", "triangle", "percentile", "tiled"];
+
+    /// One of the methods by name with its default parameters
+    pub fn method(name: &str) -> Option<Self> {
+        match name {
+            "fixed" => Some(ThresholdSelection::Fixed),
+            "otsu" => Some(ThresholdSelection::Otsu),
+            "triangle" => Some(ThresholdSelection::Triangle),
+            "percentile" => Some(ThresholdSelection::Percentile {
+                percentile: default_percentile(),
+            }),
+            "tiled" => Some(ThresholdSelection::Tiled {
+                tile_size: default_tile_size(),
+                minimum_threshold: default_minimum_threshold(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The name of the method
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThresholdSelection::Fixed => "fixed",
+            ThresholdSelection::Otsu => "otsu",
+            ThresholdSelection::Triangle => "triangle",
+            ThresholdSelection::Percentile { .. } => "percentile",
+            ThresholdSelection::Tiled { .. } => "tiled",
+        }
+    }
+}
+
+fn default_percentile() -> f32 {
+    90.0
+}
+
+fn default_tile_size() -> u32 {
+    64
+}
+
+fn default_minimum_threshold() -> f32 {
+    20.0
+}
+
+/// Where the orientation of the edges is taken from by [`ExclusionMode::Directional`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrientationSource {
+    /// The direction of the gradient of the image at each edge pixel
+    #[default]
+    Local,
+    /// The direction of the flake found by the graphene angles stage that the edge belongs to,
+    /// or the local orientation for edges outside of every flake
+    Flakes,
+}
+
+/// How the exclusion radius of an edge depends on its angle to the surface normal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AngleWeighting {
+    /// The full radius within the tolerance
+    #[default]
+    Restrict,
+    /// The radius scaled by the cosine of the angle within the tolerance
+    Cosine,
+}
+
+/// The footprint of a bacterium used by [`ExclusionMode::Bacterium`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BacteriumShape {
+    /// A rectangle with semicircular ends, as rod-shaped bacteria such as E. coli
+    #[default]
+    Capsule,
+    Ellipse,
+}
+
+/// The shape of the zone around the edges where bacteria are excluded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ExclusionMode {
+    /// Everything within the exclusion radius of an edge, whatever its orientation
+    #[default]
+    Isotropic,
+    /// Only edges running along the surface normal, such as vertically standing flakes, exclude
+    /// bacteria, or edges are weighted by how close to it they are
+    Directional {
+        #[serde(default)]
+        orientation: OrientationSource,
+        /// The direction of the surface normal in the image (degrees), 0 is horizontal and 90
+        /// vertical as for the flake angles
+        #[serde(default = "default_surface_normal")]
+        surface_normal: f32,
+        /// The largest angle between an edge and the surface normal that excludes bacteria
+        /// (degrees)
+        #[serde(default = "default_tolerance")]
+        tolerance: f32,
+        #[serde(default)]
+        weighting: AngleWeighting,
+    },
+    /// A bacterium is excluded wherever any part of it would touch an edge, not only its center.
+    /// The ratio is the fraction of the positions and orientations where it can't be placed
+    Bacterium {
+        #[serde(default)]
+        shape: BacteriumShape,
+        /// The length of the bacterium (μm)
+        #[serde(default = "default_bacterium_length")]
+        length: f32,
+        /// The width of the bacterium (μm)
+        #[serde(default = "default_bacterium_width")]
+        width: f32,
+        /// The number of orientations tried, evenly spaced over half a turn
+        #[serde(default = "default_orientations")]
+        orientations: u32,
+    },
+}
+
+impl ExclusionMode {
+    /// The names of the methods
+    pub const METHODS: [&'static str; 3] = ["isotropic", "directional", "bacterium"];
+
+    /// One of the methods by name with its default parameters
+    pub fn method(name: &str) -> Option<Self> {
+        match name {
+            "isotropic" => Some(ExclusionMode::Isotropic),
+            "directional" => Some(ExclusionMode::Directional {
+                orientation: OrientationSource::default(),
+                surface_normal: default_surface_normal(),
+                tolerance: default_tolerance(),
+                weighting: AngleWeighting::default(),
+            }),
+            "bacterium" => Some(ExclusionMode::Bacterium {
+                shape: BacteriumShape::default(),
+                length: default_bacterium_length(),
+                width: default_bacterium_width(),
+                orientations: default_orientations(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the flakes of the graphene angles stage are needed
+    pub fn uses_flakes(&self) -> bool {
+        matches!(
+            self,
+            ExclusionMode::Directional {
+                orientation: OrientationSource::Flakes,
+                ..
+            }
+        )
+    }
+}
+
+fn default_surface_normal() -> f32 {
+    90.0
+}
+
+fn default_tolerance() -> f32 {
+    30.0
+}
+
+fn default_bacterium_length() -> f32 {
+    2.0
+}
+
+fn default_bacterium_width() -> f32 {
+    0.5
+}
+
+fn default_orientations() -> u32 {
+    12
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct BacteriaExclusion {
     pub enabled: bool,
     pub contrast_threshold: f32,
+    /// The minimum area of an edge (px), or its number of pixels for the thin edges of Canny and
+    /// the Laplacian of Gaussian
     pub minimum_edge_area: usize,
     pub exclusion_radius: f32,
     pub radius_adjusted: bool,
+    #[serde(default)]
+    pub threshold_selection: ThresholdSelection,
+    #[serde(default)]
+    pub edge_detector: EdgeDetector,
+    #[serde(default)]
+    pub exclusion_mode: ExclusionMode,
+}
+
+impl BacteriaExclusion {
+    /// Whether the threshold selection applies to the edge detector, which thresholds the
+    /// strength of the contrast and gradient detectors
+    pub fn selects_threshold(&self) -> bool {
+        matches!(
+            self.edge_detector,
+            EdgeDetector::Contrast | EdgeDetector::Gradient { .. }
+        )
+    }
+
+    /// Whether `contrast_threshold` affects the edges, which it only does for the contrast
+    /// detector with a fixed threshold
+    pub fn uses_contrast_threshold(&self) -> bool {
+        self.edge_detector == EdgeDetector::Contrast
+            && self.threshold_selection == ThresholdSelection::Fixed
+    }
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
@@ -32,6 +522,23 @@ pub struct GrapheneAngles {
     pub min_graphene_ratio: f32,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct BacteriaDetection {
+    pub enabled: bool,
+    /// The standard deviation of the blur before thresholding (px), 0 to skip it
+    pub blur: f32,
+    /// The brightness separating the bacteria from the surface
+    pub threshold: u8,
+    /// Whether the bacteria are lighter or darker than the surface
+    pub polarity: Polarity,
+    /// The range of the length of a bacterium (μm)
+    pub min_length: f32,
+    pub max_length: f32,
+    /// The range of the width of a bacterium (μm)
+    pub min_width: f32,
+    pub max_width: f32,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Configuration {
@@ -40,6 +547,7 @@ pub struct Configuration {
     pub text_recognition: TextRecognition,
     pub bacteria_exclusion: BacteriaExclusion,
     pub graphene_angles: GrapheneAngles,
+    pub bacteria_detection: BacteriaDetection,
 }
 
 impl Default for Configuration {
@@ -50,6 +558,13 @@ impl Default for Configuration {
                 equalize_histogram: false,
             },
             text_recognition: TextRecognition {
+                use_metadata: true,
+                engine: OcrEngine::Tesseract,
+                profile: default_profile(),
+                profiles: BTreeMap::new(),
+                minimum_confidence: default_minimum_confidence(),
+                low_confidence: ConfidencePolicy::Warn,
+                magnification_reference: None,
                 override_scale: false,
                 scale_bar_height: 0,
                 override_scale_micrometers: 0.0,
@@ -61,6 +576,9 @@ impl Default for Configuration {
                 minimum_edge_area: 5,
                 exclusion_radius: 0.9,
                 radius_adjusted: false,
+                threshold_selection: ThresholdSelection::Fixed,
+                edge_detector: EdgeDetector::Contrast,
+                exclusion_mode: ExclusionMode::Isotropic,
             },
             graphene_angles: GrapheneAngles {
                 enabled: false,
@@ -69,6 +587,32 @@ impl Default for Configuration {
                 min_graphene_size: 0.5,
                 min_graphene_ratio: 3.0,
             },
+            bacteria_detection: BacteriaDetection {
+                enabled: false,
+                blur: 1.0,
+                threshold: 150,
+                polarity: Polarity::Light,
+                min_length: 0.5,
+                max_length: 10.0,
+                min_width: 0.2,
+                max_width: 2.0,
+            },
         }
     }
 }
+
+impl Configuration {
+    /// The SHA-256 of the configuration serialized as TOML (the contents of the exported
+    /// `config.toml`), identifying the parameters used for an analysis
+    pub fn hash(&self) -> String {
+        let serialized =
+            toml::to_string_pretty(self).expect("Failed to serialize the configuration");
+
+        Sha256::digest(serialized.as_bytes())
+            .iter()
+            .fold(String::new(), |mut hash, byte| {
+                write!(hash, "{byte:02x}").unwrap();
+                hash
+            })
+    }
+}
@@ -0,0 +1,253 @@
+use glob::{MatchOptions, Pattern};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+/// The options matching glob patterns, case insensitive like the extensions
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// An image to analyse in a batch
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target {
+    /// The path to the image
+    pub path: PathBuf,
+    /// The name of the image in the output directory, the path relative to the searched
+    /// directory without the extension and with the separators replaced by '_'. Names shared by
+    /// several images are made unique by [`make_unique`]
+    pub name: String,
+}
+
+impl Target {
+    fn new(path: PathBuf, relative: &Path) -> Self {
+        let name = relative
+            .with_extension("")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("_");
+
+        Target { path, name }
+    }
+
+    /// A target named by the file name alone, such as a file given on the command line
+    fn file(path: PathBuf) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} doesn't name a file", path.display()),
+            )
+        })?;
+        let name = PathBuf::from(name);
+
+        Ok(Target::new(path, &name))
+    }
+}
+
+/// Which files in the searched directories are analysed
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// The extensions of images, compared case insensitively
+    pub extensions: Vec<String>,
+    /// Only files with a relative path matching one of the patterns are kept, if any are given
+    pub include: Vec<Pattern>,
+    /// Files with a relative path matching any of the patterns are skipped
+    pub exclude: Vec<Pattern>,
+}
+
+impl Default for Selection {
+    /// TIFF, PNG and JPEG images without any patterns
+    fn default() -> Self {
+        Selection {
+            extensions: ["tif", "tiff", "png", "jpg", "jpeg"]
+                .map(String::from)
+                .to_vec(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl Selection {
+    /// Whether a file found in a directory should be analysed, by its path relative to the
+    /// directory
+    fn selects(&self, relative: &Path) -> bool {
+        let extension = relative.extension().and_then(OsStr::to_str);
+        let is_image = extension.is_some_and(|extension| {
+            self.extensions
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(extension))
+        });
+
+        is_image
+            && (self.include.is_empty()
+                || self
+                    .include
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(relative, MATCH_OPTIONS)))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| pattern.matches_path_with(relative, MATCH_OPTIONS))
+    }
+}
+
+/// Finds the images to analyse in sorted order. Directories are searched recursively for files
+/// picked by the selection, files are always analysed and `-` reads a list of files from stdin
+/// (one per line)
+pub fn discover(paths: &[PathBuf], selection: &Selection) -> io::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for path in paths {
+        if path.as_os_str() == "-" {
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    targets.push(Target::file(PathBuf::from(line))?);
+                }
+            }
+        } else if path.is_dir() {
+            search(path, path, selection, &mut targets)?;
+        } else if path.is_file() {
+            targets.push(Target::file(path.clone())?);
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} doesn't exist", path.display()),
+            ));
+        }
+    }
+
+    // Sort the images in alphabetical order for easier interpretation
+    targets.sort_unstable();
+    targets.dedup_by(|a, b| a.path == b.path);
+    make_unique(&mut targets);
+
+    Ok(targets)
+}
+
+/// Renames the targets sharing a name so their outputs don't overwrite each other, such as
+/// `a/b.tif` and `a_b.tif`, `img.tif` and `img.png` or the same file in two directories. The
+/// extension is added first, then a counter in the (sorted) order of the targets
+fn make_unique(targets: &mut [Target]) {
+    let mut counts = BTreeMap::<String, usize>::new();
+    for target in targets.iter() {
+        *counts.entry(target.name.clone()).or_default() += 1;
+    }
+
+    let mut taken: BTreeSet<String> = counts
+        .iter()
+        .filter(|(_, count)| **count == 1)
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for target in targets.iter_mut() {
+        if counts[&target.name] == 1 {
+            continue;
+        }
+
+        let mut name = match target.path.extension() {
+            Some(extension) => format!("{}_{}", target.name, extension.to_string_lossy()),
+            None => target.name.clone(),
+        };
+        let base = name.clone();
+        let mut counter = 1;
+        while taken.contains(&name) {
+            counter += 1;
+            name = format!("{base}_{counter}");
+        }
+
+        taken.insert(name.clone());
+        target.name = name;
+    }
+}
+
+/// Adds the selected files in the directory and all its subdirectories to the targets. Symbolic
+/// links to directories are not followed, so links back up the tree can't cause endless recursion
+fn search(
+    root: &Path,
+    directory: &Path,
+    selection: &Selection,
+    targets: &mut Vec<Target>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            search(root, &path, selection, targets)?;
+        } else if path.is_file() {
+            let relative = path.strip_prefix(root).unwrap().to_path_buf();
+            if selection.selects(&relative) {
+                targets.push(Target::new(path, &relative));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{make_unique, Target};
+
+    fn target(path: &str, name: &str) -> Target {
+        Target {
+            path: PathBuf::from(path),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn shared_names_are_made_unique() {
+        let mut targets = vec![
+            target("a/b.tif", "a_b"),
+            target("a_b.tif", "a_b"),
+            target("img.png", "img"),
+            target("img.tif", "img"),
+            target("x/c.tif", "c"),
+            target("y/c.tif", "c"),
+            target("d.tif", "d"),
+        ];
+
+        make_unique(&mut targets);
+
+        let names: Vec<_> = targets.iter().map(|target| target.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "a_b_tif",
+                "a_b_tif_2",
+                "img_png",
+                "img_tif",
+                "c_tif",
+                "c_tif_2",
+                "d"
+            ]
+        );
+    }
+
+    #[test]
+    fn counters_skip_existing_names() {
+        let mut targets = vec![
+            target("a/b", "a_b"),
+            target("a_b", "a_b"),
+            target("a_b_2.tif", "a_b_2"),
+        ];
+
+        make_unique(&mut targets);
+
+        let names: Vec<_> = targets.iter().map(|target| target.name.as_str()).collect();
+        assert_eq!(names, ["a_b", "a_b_3", "a_b_2"]);
+    }
+}
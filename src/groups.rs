@@ -0,0 +1,332 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, StudentsT};
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::summary::SummaryRow;
+
+/// How the images of a batch are assigned to samples or conditions
+#[derive(Debug, Clone)]
+pub enum Grouping {
+    /// By the name of the directory containing the image
+    Directory,
+    /// By the captures of a regular expression matched against the filename, the capture named
+    /// `group` if there is one, otherwise all captures joined by '_' (or the entire match)
+    Pattern(Regex),
+    /// By a table of images and their groups, with the directory of the table that relative
+    /// images are resolved against
+    Manifest {
+        directory: PathBuf,
+        entries: Vec<ManifestEntry>,
+    },
+}
+
+/// A row of a group manifest, a CSV file with `image` and `group` columns
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestEntry {
+    /// The path of the image, which may be absolute, relative to the manifest or only the end of
+    /// the path such as the filename
+    pub image: PathBuf,
+    pub group: String,
+}
+
+impl Grouping {
+    /// Reads a manifest assigning images to groups
+    pub fn manifest(path: &Path) -> csv::Result<Self> {
+        let entries = csv::Reader::from_path(path)?
+            .deserialize()
+            .collect::<csv::Result<_>>()?;
+        let directory = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        Ok(Grouping::Manifest { directory, entries })
+    }
+
+    /// The group of an image, if it belongs to one
+    pub fn group(&self, path: &Path) -> Option<String> {
+        match self {
+            Grouping::Directory => path
+                .parent()
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string()),
+            Grouping::Pattern(regex) => {
+                let filename = path.file_name()?.to_string_lossy();
+                let captures = regex.captures(&filename)?;
+
+                if let Some(group) = captures.name("group") {
+                    return Some(group.as_str().to_string());
+                }
+
+                if captures.len() == 1 {
+                    Some(captures[0].to_string())
+                } else {
+                    Some(
+                        captures
+                            .iter()
+                            .skip(1)
+                            .flatten()
+                            .map(|capture| capture.as_str())
+                            .collect::<Vec<_>>()
+                            .join("_"),
+                    )
+                }
+            }
+            Grouping::Manifest { directory, entries } => {
+                let path = normalize(&std::path::absolute(path).ok()?);
+
+                // An entry resolving to the image itself takes precedence over the longest one
+                // that only matches the end of its path, e.g. a filename matching images in any
+                // directory
+                entries
+                    .iter()
+                    .find(|entry| normalize(&directory.join(&entry.image)) == path)
+                    .or_else(|| {
+                        entries
+                            .iter()
+                            .map(|entry| (normalize(&entry.image), entry))
+                            .filter(|(image, _)| path.ends_with(image))
+                            .max_by_key(|(image, _)| image.components().count())
+                            .map(|(_, entry)| entry)
+                    })
+                    .map(|entry| entry.group.clone())
+            }
+        }
+    }
+}
+
+/// Removes the `.` components of a path and the `..` components that follow a directory,
+/// without accessing the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// The descriptive statistics of a metric within a group of images
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupStatistics {
+    pub group: String,
+    pub metric: &'static str,
+    /// The number of images with a value for the metric
+    pub count: usize,
+    pub mean: f64,
+    /// The sample standard deviation, if there are at least two images
+    pub standard_deviation: Option<f64>,
+    pub standard_error: Option<f64>,
+    /// The bounds of the 95% confidence interval of the mean (from the t-distribution)
+    pub ci_lower: Option<f64>,
+    pub ci_upper: Option<f64>,
+}
+
+impl GroupStatistics {
+    /// Calculates the statistics of the values, if there are any
+    pub fn new(group: String, metric: &'static str, values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+
+        let mut statistics = GroupStatistics {
+            group,
+            metric,
+            count: values.len(),
+            mean,
+            standard_deviation: None,
+            standard_error: None,
+            ci_lower: None,
+            ci_upper: None,
+        };
+
+        if values.len() >= 2 {
+            let standard_deviation =
+                (values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0)).sqrt();
+            let standard_error = standard_deviation / count.sqrt();
+            let t = StudentsT::new(0.0, 1.0, count - 1.0)
+                .unwrap()
+                .inverse_cdf(0.975);
+
+            statistics.standard_deviation = Some(standard_deviation);
+            statistics.standard_error = Some(standard_error);
+            statistics.ci_lower = Some(mean - t * standard_error);
+            statistics.ci_upper = Some(mean + t * standard_error);
+        }
+
+        Some(statistics)
+    }
+
+    /// Calculates the statistics of axial angles in radians, where opposite directions are the
+    /// same. The doubled angles are averaged as unit vectors like in the flake statistics, and
+    /// the standard deviation is the circular one of the axes. The standard error and confidence
+    /// interval are left out as they assume linear values
+    pub fn axial(group: String, metric: &'static str, angles: &[f64]) -> Option<Self> {
+        if angles.is_empty() {
+            return None;
+        }
+
+        let count = angles.len() as f64;
+        let cos = angles.iter().map(|angle| (2.0 * angle).cos()).sum::<f64>() / count;
+        let sin = angles.iter().map(|angle| (2.0 * angle).sin()).sum::<f64>() / count;
+        let length = cos.hypot(sin);
+
+        Some(GroupStatistics {
+            group,
+            metric,
+            count: angles.len(),
+            mean: sin.atan2(cos) / 2.0,
+            standard_deviation: (angles.len() >= 2 && f64::EPSILON < length)
+                .then(|| (-2.0 * length.ln()).sqrt() / 2.0),
+            standard_error: None,
+            ci_lower: None,
+            ci_upper: None,
+        })
+    }
+}
+
+/// Reads a metric from a summary row, if the image has a value for it
+pub type Metric = fn(&SummaryRow) -> Option<f64>;
+
+/// The metrics of the summary rows that are aggregated per group and compared between groups
+/// with linear statistics
+pub const METRICS: [(&str, Metric); 7] = [
+    ("exclusion_ratio", |row| row.exclusion_ratio.map(f64::from)),
+    ("flake_count", |row| {
+        row.flake_count.map(|count| count as f64)
+    }),
+    ("mean_length", |row| row.mean_length.map(f64::from)),
+    ("order_parameter", |row| row.order_parameter.map(f64::from)),
+    ("bacteria_count", |row| {
+        row.bacteria_count.map(|count| count as f64)
+    }),
+    ("bacteria_density", |row| {
+        row.bacteria_density.map(f64::from)
+    }),
+    ("bacteria_coverage", |row| {
+        row.bacteria_coverage.map(f64::from)
+    }),
+];
+
+/// The metrics of the summary rows that are axial angles in radians, which are aggregated per
+/// group with [`GroupStatistics::axial`]
+pub const AXIAL_METRICS: [(&str, Metric); 1] =
+    [("mean_angle", |row| row.mean_angle.map(f64::from))];
+
+/// Calculates the statistics of every metric in every group (in alphabetical order) from the
+/// successfully analysed images with a group
+pub fn statistics(rows: &[SummaryRow]) -> Vec<GroupStatistics> {
+    let mut groups: BTreeMap<&str, Vec<&SummaryRow>> = BTreeMap::new();
+    for row in rows {
+        if let Some(group) = &row.group {
+            groups.entry(group).or_default().push(row);
+        }
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(group, rows)| {
+            let values =
+                |value: &Metric| -> Vec<f64> { rows.iter().filter_map(|row| value(row)).collect() };
+
+            let linear = METRICS.iter().filter_map(|(metric, value)| {
+                GroupStatistics::new(group.to_string(), metric, &values(value))
+            });
+            let axial = AXIAL_METRICS.iter().filter_map(|(metric, value)| {
+                GroupStatistics::axial(group.to_string(), metric, &values(value))
+            });
+
+            linear.chain(axial).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Writes the statistics of the groups as CSV with one row per group and metric
+pub fn write(path: &Path, statistics: &[GroupStatistics]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    for row in statistics {
+        writer.serialize(row)?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use std::path::{Path, PathBuf};
+
+    use super::{GroupStatistics, Grouping, ManifestEntry};
+
+    #[test]
+    fn axial_mean_wraps_around() {
+        // Angles just either side of vertical average to vertical, not horizontal
+        let statistics = GroupStatistics::axial(
+            "a".to_string(),
+            "mean_angle",
+            &[FRAC_PI_2 - 0.1, 0.1 - FRAC_PI_2],
+        )
+        .unwrap();
+
+        assert!((statistics.mean.abs() - FRAC_PI_2).abs() < 1e-9);
+        assert!((statistics.standard_deviation.unwrap() - 0.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn perpendicular_axes_have_no_standard_deviation() {
+        let statistics =
+            GroupStatistics::axial("a".to_string(), "mean_angle", &[FRAC_PI_4, -FRAC_PI_4])
+                .unwrap();
+
+        assert_eq!(statistics.count, 2);
+        assert!(statistics.standard_deviation.is_none());
+        assert!(statistics.ci_lower.is_none());
+    }
+
+    #[test]
+    fn manifest_entries_are_normalized() {
+        let entry = |image: &str, group: &str| ManifestEntry {
+            image: PathBuf::from(image),
+            group: group.to_string(),
+        };
+        let grouping = Grouping::Manifest {
+            directory: PathBuf::from("/data"),
+            entries: vec![
+                entry("b.tif", "filename"),
+                entry("./a/b.tif", "relative"),
+                entry("../other/./c.tif", "parent"),
+            ],
+        };
+
+        let group = |path: &str| grouping.group(Path::new(path));
+        assert_eq!(group("/data/a/b.tif").as_deref(), Some("relative"));
+        assert_eq!(group("/data/a/./x/../b.tif").as_deref(), Some("relative"));
+        assert_eq!(group("/elsewhere/a/b.tif").as_deref(), Some("relative"));
+        assert_eq!(group("/elsewhere/b.tif").as_deref(), Some("filename"));
+        assert_eq!(group("/other/c.tif").as_deref(), Some("parent"));
+        assert_eq!(group("/data/c.tif"), None);
+    }
+}
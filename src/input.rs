@@ -0,0 +1,381 @@
+use image::{load_from_memory, GrayImage, ImageError};
+
+use std::{fmt, fs, path::Path};
+
+use crate::units::{micrometers_per_unit, parse_length};
+
+/// The private TIFF tag where Zeiss microscopes store their settings (`CZ_SEM`)
+const ZEISS_TAG: u16 = 34118;
+
+/// The private TIFF tag where FEI / Thermo Fisher microscopes store their settings
+const FEI_TAG: u16 = 34682;
+
+/// An image to analyse together with what is known about it from its metadata
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub image: GrayImage,
+    /// The size of a pixel as stored by the microscope, if any
+    pub pixel_size: Option<PixelSize>,
+    /// The magnification as stored by the microscope, if any
+    pub magnification: Option<f32>,
+}
+
+/// The size of a pixel as stored by the microscope in the image metadata
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelSize {
+    /// The width of a pixel in micrometers
+    pub micrometers: f32,
+    /// The microscope vendor whose metadata format the size was read from
+    pub vendor: Vendor,
+}
+
+/// The vendors whose metadata formats are understood
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vendor {
+    Zeiss,
+    Fei,
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Vendor::Zeiss => write!(f, "Zeiss"),
+            Vendor::Fei => write!(f, "FEI"),
+        }
+    }
+}
+
+impl Input {
+    /// Loads an image as grayscale together with the pixel size from its metadata. Images
+    /// without (understood) metadata are loaded without a pixel size
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let bytes = fs::read(path).map_err(ImageError::IoError)?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Loads an image as grayscale together with the pixel size from the encoded file contents,
+    /// such as an uploaded file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        Ok(Input {
+            image: load_from_memory(bytes)?.to_luma8(),
+            pixel_size: read_pixel_size(bytes),
+            magnification: read_magnification(bytes),
+        })
+    }
+}
+
+impl From<GrayImage> for Input {
+    fn from(image: GrayImage) -> Self {
+        Input {
+            image,
+            pixel_size: None,
+            magnification: None,
+        }
+    }
+}
+
+/// Reads the pixel size from the private tags of SEM microscopes in a TIFF file
+pub fn read_pixel_size(tiff: &[u8]) -> Option<PixelSize> {
+    if let Some(micrometers) = read_tag(tiff, ZEISS_TAG).and_then(|text| parse_zeiss(&text)) {
+        return Some(PixelSize {
+            micrometers,
+            vendor: Vendor::Zeiss,
+        });
+    }
+
+    if let Some(micrometers) = read_tag(tiff, FEI_TAG).and_then(|text| parse_fei(&text)) {
+        return Some(PixelSize {
+            micrometers,
+            vendor: Vendor::Fei,
+        });
+    }
+
+    None
+}
+
+/// Reads the magnification from the private tags of SEM microscopes in a TIFF file. Only Zeiss
+/// stores it, FEI stores the field width which is covered by the pixel size
+pub fn read_magnification(tiff: &[u8]) -> Option<f32> {
+    read_tag(tiff, ZEISS_TAG).and_then(|text| parse_zeiss_magnification(&text))
+}
+
+/// Parses the pixel size in micrometers from the Zeiss `CZ_SEM` text. The text consists of
+/// parameter names on their own line followed by a line with a description and value, e.g.
+/// `AP_PIXEL_SIZE` followed by "Pixel Size = 3.721 nm"
+pub fn parse_zeiss(text: &str) -> Option<f32> {
+    let mut lines = text.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        if line == "AP_PIXEL_SIZE" {
+            let (_, value) = lines.next()?.split_once('=')?;
+            return parse_length(value).filter(|size| 0.0 < *size);
+        }
+    }
+
+    None
+}
+
+/// Parses the magnification from the Zeiss `CZ_SEM` text, stored as `AP_MAG` followed by e.g.
+/// "Mag = 10.00 K X"
+pub fn parse_zeiss_magnification(text: &str) -> Option<f32> {
+    let mut lines = text.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        if line == "AP_MAG" {
+            let (_, value) = lines.next()?.split_once('=')?;
+            let value = value.trim().trim_end_matches(['X', 'x']).trim_end();
+            let (number, factor) = match value.strip_suffix(['K', 'k']) {
+                Some(number) => (number, 1000.0),
+                None => (value, 1.0),
+            };
+
+            let magnification: f32 = number.trim().parse().ok()?;
+            return Some(magnification * factor).filter(|magnification| 0.0 < *magnification);
+        }
+    }
+
+    None
+}
+
+/// Parses the pixel size in micrometers from the FEI INI style text, where it is stored in
+/// meters as `PixelWidth` in the `[Scan]` section
+pub fn parse_fei(text: &str) -> Option<f32> {
+    let mut section = "";
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line;
+        } else if section == "[Scan]" {
+            if let Some(("PixelWidth", value)) = line.split_once('=') {
+                let meters: f32 = value.trim().parse().ok()?;
+                return Some(meters * micrometers_per_unit("m")?).filter(|size| 0.0 < *size);
+            }
+        }
+    }
+
+    None
+}
+
+/// Reads the raw value of a tag in the first image file directory of a TIFF file as text, which
+/// is decoded as Latin-1 if it isn't valid UTF-8 (as for the "µ" in Zeiss files). Only tags with
+/// byte sized values are supported, which is what microscopes use for their private text tags
+fn read_tag(tiff: &[u8], tag: u16) -> Option<String> {
+    // The byte order is given by the first two bytes
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    // Only classic TIFF is supported, not BigTIFF
+    if u16_at(2)? != 42 {
+        return None;
+    }
+
+    let directory = u32_at(4)? as usize;
+    let entry_count = u16_at(directory)? as usize;
+
+    for i in 0..entry_count {
+        let entry = directory + 2 + i * 12;
+        if u16_at(entry)? != tag {
+            continue;
+        }
+
+        // Only byte sized types are supported (BYTE, ASCII, SBYTE and UNDEFINED)
+        if !matches!(u16_at(entry + 2)?, 1 | 2 | 6 | 7) {
+            return None;
+        }
+
+        // Values of at most four bytes are stored in the entry itself
+        let count = u32_at(entry + 4)? as usize;
+        let offset = if count <= 4 {
+            entry + 8
+        } else {
+            u32_at(entry + 8)? as usize
+        };
+
+        let bytes = tiff.get(offset..offset.checked_add(count)?)?;
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => bytes.iter().map(|byte| char::from(*byte)).collect(),
+        };
+
+        return Some(text.trim_end_matches('\0').to_string());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_fei, parse_zeiss, parse_zeiss_magnification, read_magnification, read_pixel_size,
+        read_tag, PixelSize, Vendor, FEI_TAG, ZEISS_TAG,
+    };
+
+    /// Builds a TIFF file with a single image file directory holding the given ASCII tags, with
+    /// the values stored after the directory
+    fn tiff(little_endian: bool, tags: &[(u16, &[u8])]) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+
+        let mut file: Vec<u8> = Vec::new();
+        file.extend(if little_endian { b"II" } else { b"MM" });
+        file.extend(u16_bytes(42));
+        file.extend(u32_bytes(8));
+
+        // The directory, followed by the offset of the next one (none)
+        let mut offset = 8 + 2 + tags.len() * 12 + 4;
+        let mut values: Vec<u8> = Vec::new();
+        file.extend(u16_bytes(tags.len() as u16));
+        for (tag, value) in tags {
+            file.extend(u16_bytes(*tag));
+            file.extend(u16_bytes(2));
+            file.extend(u32_bytes(value.len() as u32));
+            if value.len() <= 4 {
+                let mut inline = value.to_vec();
+                inline.resize(4, 0);
+                file.extend(inline);
+            } else {
+                file.extend(u32_bytes(offset as u32));
+                values.extend(*value);
+                offset += value.len();
+            }
+        }
+        file.extend(u32_bytes(0));
+        file.extend(values);
+
+        file
+    }
+
+    const ZEISS_TEXT: &[u8] =
+        b"AP_PIXEL_SIZE\r\nImage Pixel Size = 12.3 nm\r\nAP_MAG\r\nMag = 10.00 K X\r\n\0";
+    const FEI_TEXT: &[u8] = b"[User]\r\nUser=lab\r\n[Scan]\r\nPixelWidth=1.2e-08\r\n\0";
+
+    #[test]
+    fn reads_tags_in_both_byte_orders() {
+        for little_endian in [true, false] {
+            let file = tiff(little_endian, &[(256, b"abc"), (ZEISS_TAG, ZEISS_TEXT)]);
+
+            assert_eq!(read_tag(&file, 256).as_deref(), Some("abc"));
+            assert_eq!(
+                read_tag(&file, ZEISS_TAG).as_deref(),
+                std::str::from_utf8(&ZEISS_TEXT[..ZEISS_TEXT.len() - 1]).ok()
+            );
+            assert_eq!(read_tag(&file, FEI_TAG), None);
+        }
+    }
+
+    #[test]
+    fn reads_the_pixel_size_of_both_vendors() {
+        for little_endian in [true, false] {
+            let zeiss = read_pixel_size(&tiff(little_endian, &[(ZEISS_TAG, ZEISS_TEXT)])).unwrap();
+            assert_eq!(zeiss.vendor, Vendor::Zeiss);
+            assert!((zeiss.micrometers - 0.0123).abs() < 1e-7);
+
+            let fei = read_pixel_size(&tiff(little_endian, &[(FEI_TAG, FEI_TEXT)])).unwrap();
+            assert_eq!(fei.vendor, Vendor::Fei);
+            assert!((fei.micrometers - 0.012).abs() < 1e-7);
+        }
+
+        assert_eq!(
+            read_magnification(&tiff(true, &[(ZEISS_TAG, ZEISS_TEXT)])),
+            Some(10_000.0)
+        );
+    }
+
+    #[test]
+    fn decodes_latin_1_text() {
+        // The micro sign is a single byte in Latin-1
+        let file = tiff(
+            true,
+            &[(ZEISS_TAG, b"AP_PIXEL_SIZE\nPixel Size = 1.5 \xb5m\n")],
+        );
+
+        assert_eq!(
+            read_pixel_size(&file),
+            Some(PixelSize {
+                micrometers: 1.5,
+                vendor: Vendor::Zeiss
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = tiff(true, &[(ZEISS_TAG, ZEISS_TEXT)]);
+
+        // Cut inside the header, the directory and the value
+        for length in [3, 12, 20, file.len() - 10] {
+            assert_eq!(read_pixel_size(&file[..length]), None, "length {length}");
+        }
+
+        // A directory offset past the end of the file
+        let mut file = file;
+        file[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_pixel_size(&file), None);
+
+        // Neither TIFF nor BigTIFF
+        assert_eq!(read_pixel_size(b"PNG image"), None);
+        assert_eq!(read_pixel_size(b"II\x2b\0\x08\0\0\0"), None);
+    }
+
+    #[test]
+    fn parses_the_vendor_text() {
+        assert!(
+            (parse_zeiss("AP_PIXEL_SIZE\nImage Pixel Size = 12.3 nm").unwrap() - 0.0123).abs()
+                < 1e-7
+        );
+        assert_eq!(
+            parse_zeiss("AP_PIXEL_SIZE\nImage Pixel Size = 2 µm"),
+            Some(2.0)
+        );
+        assert_eq!(parse_zeiss("AP_PIXEL_SIZE\nImage Pixel Size = 0 nm"), None);
+        assert_eq!(parse_zeiss("AP_WIDTH\nWidth = 12.3 µm"), None);
+
+        assert!((parse_fei("[Scan]\nPixelWidth=1.2e-08").unwrap() - 0.012).abs() < 1e-7);
+        assert_eq!(parse_fei("[Image]\nPixelWidth=1.2e-08"), None);
+        assert_eq!(parse_fei("[Scan]\nPixelWidth=wide"), None);
+
+        assert_eq!(
+            parse_zeiss_magnification("AP_MAG\nMag = 10.00 K X"),
+            Some(10_000.0)
+        );
+        assert_eq!(
+            parse_zeiss_magnification("AP_MAG\nMag = 250 X"),
+            Some(250.0)
+        );
+        assert_eq!(parse_zeiss_magnification("AP_MAG\nMag = high"), None);
+    }
+}
@@ -0,0 +1,47 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::too_many_lines,
+    clippy::cast_precision_loss,
+    clippy::cast_possible_wrap,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate,
+    clippy::module_name_repetitions
+)]
+
+//! Analysis of SEM pictures of graphene to estimate the anti-bacterial property of surfaces.
+//!
+//! The easiest way to use the library is through a [`Pipeline`], which runs all the enabled
+//! stages of the analysis on an image and collects the outcome in an [`AnalysisResult`]. The
+//! individual stages are also available in the [`algorithms`] module.
+
+/// The module containing all the actual algorithms
+pub mod algorithms;
+
+/// The module containing the destinations of intermediate images and tables
+pub mod artifacts;
+
+/// The module storing the outputs of stages to reuse them between runs
+pub mod cache;
+
+/// The module containing the different configuration parameters
+pub mod configuration;
+
+/// The module loading images and the metadata stored in them by the microscope
+pub mod input;
+
+/// The module running all the algorithms in sequence on an image
+pub mod pipeline;
+
+/// The module containing the machine-readable report of an analysis
+pub mod report;
+
+/// The module containing helpers for lengths in different units
+pub mod units;
+
+pub use configuration::Configuration;
+pub use input::Input;
+pub use pipeline::{AnalysisError, AnalysisResult, Pipeline, Timings};
+pub use report::Report;
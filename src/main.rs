@@ -8,80 +8,154 @@
     clippy::unused_async
 )]
 
+use calibrate::{Objective, Values};
 use clap::Parser;
+use discovery::{Selection, Target};
 use git_version::git_version;
-use image::open;
+use glob::Pattern;
+use graphene_analysis::{
+    artifacts::{ArtifactSink, DirectorySink, Filter},
+    cache::{DiskCache, StageCache},
+    Configuration, Input, Pipeline, Report,
+};
+use groups::Grouping;
+use manifest::Manifest;
 use rayon::prelude::*;
+use regex::Regex;
+use summary::{SummaryFormat, SummaryRow};
+use sweep::Sweep;
 
-use std::ffi::OsStr;
 use std::fmt::{Debug, Write};
-use std::{fs, net::SocketAddr, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use crate::algorithms::{bacteria_exclusion, determine_scale, graphene_angles, pre_processing};
-use crate::configuration::Configuration;
+/// The module fitting the exclusion parameters to measurements
+mod calibrate;
 
-/// The module containing all the actual algorithms
-mod algorithms;
+/// The module comparing the groups of a batch with statistical tests
+mod compare;
 
-/// The module containing the different configuration parameters
-mod configuration;
+/// The module finding the images to analyse in a batch
+mod discovery;
+
+/// The module assigning the images of a batch to groups and aggregating them
+mod groups;
+
+/// The module recording the progress of a batch to resume it
+mod manifest;
+
+/// The module containing the statistical tests between groups
+mod statistics;
+
+/// The module running the analysis for every combination of a set of parameters
+mod sweep;
+
+/// The module containing the summary table of a batch
+mod summary;
 
 /// The module containing the interactive interface
 mod web;
 
+/// The largest request body accepted by the interactive interface by default (MiB)
+const DEFAULT_MAX_BODY: u64 = 64;
+
 fn main() {
     let args = Args::parse();
 
     // Create a output directory if non exist
     fs::create_dir_all("./output/").expect("Failed to create output directory");
 
+    // Reuse the outputs of the stages from earlier runs if enabled
+    let cache = args.cache.as_deref().map(DiskCache::new);
+    let stage_cache = cache.as_ref().map(|cache| cache as &dyn StageCache);
+
     // Run in interactive mode on "127.0.0.1:8080" if no action got provided
     let action = match args.action {
         Some(action) => action,
         None => Action::Interactive {
             address: "127.0.0.1:8080".parse().unwrap(),
+            max_body: DEFAULT_MAX_BODY,
         },
     };
 
     match action {
-        Action::Analyse { config, path } => {
-            // Load the configuration file (or use the default)
-            let config = if let Some(path) = config {
-                toml::from_str(&fs::read_to_string(path).expect("Failed to read the config file"))
-                    .expect("Couldn't parse the config file as TOML")
-            } else {
-                Configuration::default()
-            };
-
-            // Warn about config using another version of the program
-            if config.program_version != git_version!() {
-                eprintln!("Warning: the config you have provided was made by another version of the program. It might not reproduce the same results (config: {}, program: {})", config.program_version, git_version!());
-            }
+        Action::Analyse {
+            config,
+            artifacts,
+            path,
+        } => {
+            let config = load_config(config.as_deref());
 
-            single(&config, &path);
+            single(&config, stage_cache, &path, artifacts.as_deref());
         }
         Action::Batch {
             config,
             discard_error,
-            path,
+            artifacts,
+            summary_format,
+            extensions,
+            include,
+            exclude,
+            group_by_directory,
+            group_pattern,
+            group_manifest,
+            resume,
+            paths,
         } => {
-            // Load the configuration file (or use the default)
-            let config = if let Some(path) = config {
-                toml::from_str(&fs::read_to_string(path).expect("Failed to read the config file"))
-                    .expect("Couldn't parse the config file as TOML")
+            let config = load_config(config.as_deref());
+
+            let selection = Selection {
+                extensions,
+                include,
+                exclude,
+            };
+
+            let grouping = if group_by_directory {
+                Some(Grouping::Directory)
+            } else if let Some(pattern) = group_pattern {
+                Some(Grouping::Pattern(pattern))
             } else {
-                Configuration::default()
+                group_manifest.map(|path| {
+                    Grouping::manifest(&path)
+                        .unwrap_or_else(|e| panic!("Couldn't read the group manifest ({e})"))
+                })
             };
 
-            // Warn about config using another version of the program
-            if config.program_version != git_version!() {
-                eprintln!("Warning: the config you have provided was made by another version of the program. It might not reproduce the same results (config: {}, program: {})", config.program_version, git_version!());
-            }
+            batch(
+                pipeline(&config, stage_cache),
+                &paths,
+                &selection,
+                grouping.as_ref(),
+                discard_error,
+                resume,
+                artifacts.as_deref(),
+                summary_format,
+            );
+        }
+        Action::Calibrate {
+            config,
+            radius,
+            threshold,
+            objective,
+            measurements,
+        } => {
+            let config = load_config(config.as_deref());
 
-            batch(&config, path, discard_error);
+            calibrate(&config, &measurements, &radius, &threshold, objective);
         }
-        Action::Interactive { address } => {
-            web::start(address);
+        Action::Compare { summary } => {
+            compare(&summary);
+        }
+        Action::Sweep { config, paths } => {
+            run_sweep(&config, stage_cache, &paths);
+        }
+        Action::Interactive { address, max_body } => {
+            web::start(address, max_body * 1024 * 1024, cache);
         }
         Action::Export { path } => {
             // Parse default configuration to TOML
@@ -94,193 +168,235 @@ fn main() {
     }
 }
 
-fn single(config: &Configuration, path: &PathBuf) {
+/// Loads the configuration file (or uses the default), warning if it was made by another version
+/// of the program
+fn load_config(path: Option<&Path>) -> Configuration {
+    let config: Configuration = if let Some(path) = path {
+        toml::from_str(&fs::read_to_string(path).expect("Failed to read the config file"))
+            .expect("Couldn't parse the config file as TOML")
+    } else {
+        Configuration::default()
+    };
+
+    // Warn about config using another version of the program
+    if config.program_version != git_version!() {
+        eprintln!("Warning: the config you have provided was made by another version of the program. It might not reproduce the same results (config: {}, program: {})", config.program_version, git_version!());
+    }
+
+    config
+}
+
+/// Creates a sink writing the intermediate images and tables of an image to the output directory,
+/// prefixed by the filename of the image. Only the listed artifacts are kept if any are provided
+fn artifact_sink(name: &str, keep: Option<&[String]>) -> (DirectorySink, Box<dyn ArtifactSink>) {
+    let directory = DirectorySink::new("./output/", name.to_string() + "_");
+
+    let sink: Box<dyn ArtifactSink> = match keep {
+        Some(names) => Box::new(Filter::new(directory.clone(), names.to_vec())),
+        None => Box::new(directory.clone()),
+    };
+
+    (directory, sink)
+}
+
+/// A pipeline reusing the outputs of the stages from the cache, if any
+fn pipeline<'a>(config: &'a Configuration, cache: Option<&'a dyn StageCache>) -> Pipeline<'a> {
+    match cache {
+        Some(cache) => Pipeline::new(config).with_cache(cache),
+        None => Pipeline::new(config),
+    }
+}
+
+fn single(
+    config: &Configuration,
+    cache: Option<&dyn StageCache>,
+    path: &PathBuf,
+    keep_artifacts: Option<&[String]>,
+) {
     // Load image
-    let image = open(path).expect("Could not load input image").to_luma8();
+    let input = Input::open(path).expect("Could not load input image");
 
-    // Create an output prefix from the filename
-    let output_prefix = "./output/".to_string() + path.file_stem().unwrap().to_str().unwrap() + "_";
+    // Export artifacts to the output directory, prefixed by the filename
+    let (output, artifacts) =
+        artifact_sink(path.file_stem().unwrap().to_str().unwrap(), keep_artifacts);
 
-    // Determine scale (um / px)
-    let (scale, um, px, scale_bar_height, image) =
-        determine_scale(image, &config.text_recognition, true, &output_prefix)
-            .expect("Failed to determine scale of image");
+    // Run all the enabled stages on the image
+    let result = pipeline(config, cache)
+        .run(input, artifacts.as_ref())
+        .unwrap_or_else(|e| panic!("{e}"));
+
+    let scale = result.scale;
     println!(
-        "Scale: {:.4} (px: {}, um: {}, height: {})",
-        scale, px, um, scale_bar_height
+        "Scale: {:.4} (px: {}, um: {}, height: {}, source: {}, confidence: {:.2})",
+        scale.scale,
+        scale.pixels,
+        scale.micrometers,
+        scale.scale_bar_height,
+        scale.source,
+        scale.confidence
     );
 
-    // Preprocessing
-    let image = pre_processing(image, config.pre_processing);
-
-    // Find graphene and determine bacteria exclusion percentage
-    if config.bacteria_exclusion.enabled {
-        let bacteria_exclusion_ratio = bacteria_exclusion(
-            &image,
-            &config.bacteria_exclusion,
-            scale,
-            true,
-            &output_prefix,
-        )
-        .expect("Calculating bacteria exclusion failed");
+    for warning in &result.warnings {
+        eprintln!("Warning: {warning}");
+    }
 
+    if let Some(exclusion) = result.bacteria_exclusion {
         println!(
             "Area within range of graphene edge (for correlation): {:.2}%",
-            100.0 * bacteria_exclusion_ratio
+            100.0 * exclusion.value()
         );
+
+        if !config.bacteria_exclusion.uses_contrast_threshold() {
+            if let Some(threshold) = exclusion.contrast_threshold {
+                println!("Contrast threshold chosen for the image: {threshold:.1}");
+            }
+        }
+
+        if let Some(placeable_ratio) = exclusion.placeable_ratio {
+            println!(
+                "Positions and orientations where a bacterium fits: {:.2}%",
+                100.0 * placeable_ratio
+            );
+        }
     }
 
-    // Find angles of graphene in the image
-    if config.graphene_angles.enabled {
-        graphene_angles(&image, &config.graphene_angles, scale, true, &output_prefix);
+    if let Some(detection) = &result.bacteria {
+        println!(
+            "Bacteria found: {} ({:.0} per mm², {:.2}% coverage)",
+            detection.count(),
+            detection.density,
+            100.0 * detection.coverage
+        );
     }
 
-    // Write the configuration to the output directory
+    // Write the configuration and the results to the output directory
     fs::write(
-        output_prefix + "config.toml",
+        output.path("config.toml"),
         toml::to_string_pretty(&config).expect("Failed to serialize default configuration"),
     )
     .expect("Couldn't write to config file");
+
+    fs::write(
+        output.path("results.json"),
+        Report::new(path.display().to_string(), config, &result).to_json(),
+    )
+    .expect("Couldn't write to results file");
 }
 
-fn batch(config: &Configuration, path: PathBuf, discard_error: bool) {
-    // Determine all target images (within the target directory)
+#[allow(clippy::too_many_arguments)]
+fn batch(
+    pipeline: Pipeline,
+    paths: &[PathBuf],
+    selection: &Selection,
+    grouping: Option<&Grouping>,
+    discard_error: bool,
+    resume: bool,
+    keep_artifacts: Option<&[String]>,
+    summary_format: SummaryFormat,
+) {
+    // Determine all target images (within the target directories)
     println!("Targets");
-    let mut targets = Vec::new();
-    for path in fs::read_dir(path).expect("Failed to read the given directory, does it exist?") {
-        let path = path.unwrap().path();
+    let targets = discovery::discover(paths, selection)
+        .unwrap_or_else(|e| panic!("Failed to find the images to analyse ({e})"));
 
-        let extension = path.extension().and_then(OsStr::to_str);
-        if extension == Some("tif") || extension == Some("tiff") {
-            targets.push(path);
-        }
+    // Print the map between image paths and ids
+    for (i, target) in targets.iter().enumerate() {
+        println!(" - {i}: {}", target.path.display());
     }
 
-    // Sort the images in alphabetical order for easier interpretation
-    targets.sort_unstable();
+    println!();
 
-    // Print the map between image paths and ids
-    for (i, path) in targets.iter().enumerate() {
-        println!(" - {i}: {}", path.display());
+    // Skip the images already analysed with the same configuration when resuming
+    let config = pipeline.config();
+    let manifest_path = Path::new("./output/manifest.jsonl");
+    let completed = if resume {
+        manifest::completed(manifest_path, &config.hash())
+            .unwrap_or_else(|e| panic!("Couldn't read the run manifest ({e})"))
+    } else {
+        BTreeMap::new()
+    };
+
+    if resume {
+        let skipped = targets
+            .iter()
+            .filter(|target| completed.contains_key(&target.path.display().to_string()))
+            .count();
+        println!(
+            "Resuming: {skipped} of {} images were already analysed with the same configuration\n",
+            targets.len()
+        );
     }
 
-    println!();
+    // Record every image as soon as it is analysed
+    let manifest = Manifest::open(manifest_path, config.hash(), resume)
+        .unwrap_or_else(|e| panic!("Couldn't create the run manifest ({e})"));
 
-    // Run the targets in parallel and aggregate statistics
-    let bacteria_exclusion_ratios: Vec<f32> = targets
+    // Run the targets in parallel, collecting one summary row per image in the order of the
+    // targets
+    let mut rows: Vec<SummaryRow> = targets
         .par_iter()
         .enumerate()
-        .filter_map(|(i, target)| -> Option<f32> {
-            // Load image
-            let image = open::<&PathBuf>(target)
-                .unwrap_or_else(|_| panic!("Could not load image {}", target.display()))
-                .to_luma8();
-
-            // Create an output prefix from the filename
-            let output_prefix =
-                "./output/".to_string() + target.file_stem().unwrap().to_str().unwrap() + "_";
-
-            // Create an output string which progressively gets more information, one for each stage
-            let mut output_string = format!("{i}: ");
-
-            // Determine scale (um / px)
-            let (scale, um, px, scale_bar_height, image) =
-                match determine_scale(image, &config.text_recognition, true, &output_prefix) {
-                    Ok(result) => result,
-                    Err(e) => {
-                        let message = format!(
-                            "{}: Failed to determine scale of image {} ({})",
-                            i,
-                            target.display(),
-                            e
-                        );
-
-                        if discard_error {
-                            println!("{message}");
-                            return None;
-                        }
-
-                        panic!("{}", message);
-                    }
-                };
-            write!(
-                output_string,
-                "Scale: {}um / {}px ({}). ",
-                um, px, scale_bar_height
-            )
-            .unwrap();
-
-            // Preprocessing
-            let image = pre_processing(image, config.pre_processing);
-
-            // Find angles of graphene in the image
-            if config.graphene_angles.enabled {
-                graphene_angles(&image, &config.graphene_angles, scale, true, &output_prefix);
-
-                write!(
-                    output_string,
-                    "Calculated graphene angles, see image or .csv file. ",
-                )
-                .unwrap();
+        .map(|(i, target)| -> SummaryRow {
+            if let Some(row) = completed.get(&target.path.display().to_string()) {
+                return row.clone();
             }
 
-            // Find graphene and determine bacteria exclusion percentage
-            let mut return_status = None;
-            if config.bacteria_exclusion.enabled {
-                let bacteria_exclusion_ratio = match bacteria_exclusion(
-                    &image,
-                    &config.bacteria_exclusion,
-                    scale,
-                    true,
-                    &output_prefix,
-                ) {
-                    Ok(result) => result,
-                    Err(e) => {
-                        let message = format!(
-                            "{} Failed to calculate bacteria exclusion for {} ({})",
-                            i,
-                            target.display(),
-                            e
-                        );
-
-                        if discard_error {
-                            println!("{message}");
-                            return None;
-                        }
-
-                        panic!("{}", message);
-                    }
-                };
-                write!(
-                    output_string,
-                    "Graphene edge area: {:.2}%. ",
-                    100.0 * bacteria_exclusion_ratio
-                )
-                .unwrap();
-
-                return_status = Some(bacteria_exclusion_ratio * 100.0);
-            }
-
-            println!("{output_string}");
-
-            // Write the configuration to the output directory
-            fs::write(
-                output_prefix + "config.toml",
-                toml::to_string_pretty(&config).expect("Failed to serialize default configuration"),
-            )
-            .expect("Couldn't write to config file");
+            let row = analyse(pipeline, i, target, discard_error, keep_artifacts);
+            manifest
+                .record(&row)
+                .unwrap_or_else(|e| panic!("Couldn't write to the run manifest ({e})"));
 
-            return_status
+            row
         })
         .collect();
 
+    // Assign the images to their groups
+    if let Some(grouping) = grouping {
+        for (row, target) in rows.iter_mut().zip(&targets) {
+            row.group = grouping.group(&target.path);
+        }
+
+        let ungrouped: Vec<_> = targets
+            .iter()
+            .zip(&rows)
+            .filter(|(_, row)| row.group.is_none())
+            .collect();
+        if !ungrouped.is_empty() {
+            eprintln!(
+                "\nWarning: {} images belong to no group and are left out of the group statistics:",
+                ungrouped.len()
+            );
+            for (target, _) in ungrouped {
+                eprintln!(" - {}", target.path.display());
+            }
+        }
+    }
+
+    // Write the summary of all images
+    let summary_path = summary_format.path("./output/");
+    summary::write(&summary_path, &rows, summary_format).expect("Couldn't write the summary");
+
     println!(
         "\nImages, both output and intermediates, have been exported to the 'output' directory"
     );
+    println!("The summary of all images is in {}", summary_path.display());
+
+    // Write the statistics of each group
+    let group_statistics = groups::statistics(&rows);
+    if grouping.is_some() {
+        groups::write(Path::new("./output/groups.csv"), &group_statistics)
+            .expect("Couldn't write the group statistics");
+        println!("The statistics of each group are in ./output/groups.csv");
+    }
 
     // Print out aggregated statistics
     println!("\nAggregated statistics:");
     if config.bacteria_exclusion.enabled {
+        let bacteria_exclusion_ratios: Vec<f32> = rows
+            .iter()
+            .filter_map(|row| row.exclusion_ratio)
+            .map(|ratio| ratio * 100.0)
+            .collect();
         let mean = mean(&bacteria_exclusion_ratios);
 
         println!(
@@ -288,7 +404,317 @@ fn batch(config: &Configuration, path: PathBuf, discard_error: bool) {
             mean,
             standard_deviation(&bacteria_exclusion_ratios, mean)
         );
+
+        for statistics in group_statistics
+            .iter()
+            .filter(|statistics| statistics.metric == "exclusion_ratio")
+        {
+            println!(
+                "   - {}: {:.2}% (n: {}, 95% CI: {})",
+                statistics.group,
+                100.0 * statistics.mean,
+                statistics.count,
+                match (statistics.ci_lower, statistics.ci_upper) {
+                    (Some(lower), Some(upper)) =>
+                        format!("{:.2}% - {:.2}%", 100.0 * lower, 100.0 * upper),
+                    _ => "-".to_string(),
+                }
+            );
+        }
+    }
+}
+
+/// Analyses an image of a batch, printing the outcome and writing its artifacts, configuration
+/// and report to the output directory
+fn analyse(
+    pipeline: Pipeline,
+    i: usize,
+    target: &Target,
+    discard_error: bool,
+    keep_artifacts: Option<&[String]>,
+) -> SummaryRow {
+    let start = Instant::now();
+    let config = pipeline.config();
+
+    // Export artifacts to the output directory, prefixed by the filename
+    let (output, artifacts) = artifact_sink(&target.name, keep_artifacts);
+
+    // Load the image and run all the enabled stages on it
+    let result = Input::open(&target.path)
+        .map_err(|e| format!("Could not load image ({e})"))
+        .and_then(|input| {
+            pipeline
+                .run(input, artifacts.as_ref())
+                .map_err(|e| e.to_string())
+        });
+
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let message = format!("{i}: {} ({e})", target.path.display());
+
+            if discard_error {
+                println!("{message}");
+                return SummaryRow::failed(&target.path, e, start.elapsed());
+            }
+
+            panic!("{}", message);
+        }
+    };
+
+    // Create an output string with information from each stage
+    let scale = result.scale;
+    let mut output_string = format!(
+        "{i}: Scale: {}um / {}px ({}, {}, confidence {:.2}). ",
+        scale.micrometers, scale.pixels, scale.scale_bar_height, scale.source, scale.confidence
+    );
+
+    for warning in &result.warnings {
+        write!(output_string, "Warning: {warning}. ").unwrap();
+    }
+
+    if result.flakes.is_some() {
+        write!(
+            output_string,
+            "Calculated graphene angles, see image or .csv file. ",
+        )
+        .unwrap();
+    }
+
+    if let Some(exclusion) = result.bacteria_exclusion {
+        write!(
+            output_string,
+            "Graphene edge area: {:.2}%. ",
+            100.0 * exclusion.value()
+        )
+        .unwrap();
+
+        if !config.bacteria_exclusion.uses_contrast_threshold() {
+            if let Some(threshold) = exclusion.contrast_threshold {
+                write!(output_string, "Contrast threshold: {threshold:.1}. ").unwrap();
+            }
+        }
+    }
+
+    if let Some(detection) = &result.bacteria {
+        write!(
+            output_string,
+            "Bacteria: {} ({:.0} per mm²). ",
+            detection.count(),
+            detection.density
+        )
+        .unwrap();
+    }
+
+    println!("{output_string}");
+
+    // Write the configuration and the results to the output directory
+    fs::write(
+        output.path("config.toml"),
+        toml::to_string_pretty(&config).expect("Failed to serialize default configuration"),
+    )
+    .expect("Couldn't write to config file");
+
+    let report = Report::new(target.path.display().to_string(), config, &result);
+    fs::write(output.path("results.json"), report.to_json())
+        .expect("Couldn't write to results file");
+
+    SummaryRow::new(&report, start.elapsed())
+}
+
+fn calibrate(
+    config: &Configuration,
+    measurements_path: &Path,
+    radii: &Values,
+    thresholds: &Values,
+    objective: Objective,
+) {
+    let measurements = calibrate::read_measurements(measurements_path)
+        .unwrap_or_else(|e| panic!("Couldn't read the measurements ({e})"));
+
+    // Trying several contrast thresholds is wasted when they don't change the edges
+    let fixed_threshold;
+    let thresholds = if config.bacteria_exclusion.uses_contrast_threshold() {
+        thresholds
+    } else {
+        if 1 < thresholds.0.len() {
+            eprintln!(
+                "Warning: the contrast threshold has no effect with the {} edge detector and {} threshold selection, only the configured one is used",
+                config.bacteria_exclusion.edge_detector.name(),
+                config.bacteria_exclusion.threshold_selection.name()
+            );
+        }
+
+        fixed_threshold = Values(vec![config.bacteria_exclusion.contrast_threshold]);
+        &fixed_threshold
+    };
+
+    println!(
+        "Calibrating {} radii and {} thresholds on {} images",
+        radii.0.len(),
+        thresholds.0.len(),
+        measurements.len()
+    );
+
+    let candidates = calibrate::calibrate(config, &measurements, radii, thresholds);
+    calibrate::write(Path::new("./output/calibration.csv"), &candidates)
+        .expect("Couldn't write the calibration");
+
+    // Pick the parameters with the strongest correlation
+    let Some((_, best)) = candidates
+        .iter()
+        .filter_map(|candidate| Some((candidate.score(objective)?, candidate)))
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+    else {
+        eprintln!("None of the parameters gave a correlation, are there at least two images with different measurements?");
+        return;
+    };
+
+    println!(
+        "Best parameters: exclusion radius {} um, contrast threshold {} (n: {}, Pearson: {:.3}, Spearman: {:.3}, R²: {:.3})",
+        best.exclusion_radius,
+        best.contrast_threshold,
+        best.count,
+        best.pearson.unwrap_or(f64::NAN),
+        best.spearman.unwrap_or(f64::NAN),
+        best.r_squared.unwrap_or(f64::NAN),
+    );
+
+    calibrate::plot(Path::new("./output/calibration.png"), best);
+
+    // Write a configuration using the best parameters
+    let mut calibrated = config.clone();
+    calibrated.bacteria_exclusion.exclusion_radius = best.exclusion_radius;
+    calibrated.bacteria_exclusion.contrast_threshold = best.contrast_threshold;
+    fs::write(
+        "./output/calibrated.toml",
+        toml::to_string_pretty(&calibrated).expect("Failed to serialize the configuration"),
+    )
+    .expect("Couldn't write to config file");
+
+    println!("\nThe correlation of every parameter set, a scatter plot and the calibrated configuration have been exported to the 'output' directory");
+}
+
+fn compare(summary_path: &Path) {
+    let rows = summary::read(summary_path)
+        .unwrap_or_else(|e| panic!("Couldn't read the summary {} ({e})", summary_path.display()));
+
+    if rows.iter().all(|row| row.group.is_none()) {
+        eprintln!("Warning: none of the images in the summary belong to a group, run the batch with one of the --group options");
+    }
+
+    let comparisons = compare::compare(&rows);
+    compare::write(Path::new("./output/comparison.csv"), &comparisons)
+        .expect("Couldn't write the comparison");
+
+    // Draw a box plot for every metric with values
+    for (metric, value) in groups::METRICS {
+        let groups = compare::group_values(&rows, value);
+        if !groups.is_empty() {
+            compare::plot(
+                &PathBuf::from(format!("./output/comparison_{metric}.png")),
+                metric,
+                &groups,
+            );
+        }
+    }
+
+    // Print the tests across all groups
+    for comparison in comparisons
+        .iter()
+        .filter(|comparison| comparison.group_a.is_none())
+    {
+        let (test, effect) = match comparison.test {
+            "anova" => ("ANOVA", "eta squared"),
+            _ => ("Watson-Williams", "circular eta squared"),
+        };
+
+        println!(
+            "{}: F = {:.3}, p = {:.4}, {effect} = {:.3} ({test})",
+            comparison.metric, comparison.statistic, comparison.p_value, comparison.effect_size
+        );
     }
+
+    println!(
+        "\nThe tests and box plots of every metric have been exported to the 'output' directory"
+    );
+}
+
+fn run_sweep(config_path: &Path, cache: Option<&dyn StageCache>, paths: &[PathBuf]) {
+    let sweep =
+        Sweep::parse(&fs::read_to_string(config_path).expect("Failed to read the config file"))
+            .expect("Couldn't parse the config file as TOML");
+
+    // Warn about config using another version of the program
+    if let Some(version) = sweep.program_version() {
+        if version != git_version!() {
+            eprintln!("Warning: the config you have provided was made by another version of the program. It might not reproduce the same results (config: {version}, program: {})", git_version!());
+        }
+    }
+
+    let configurations = sweep
+        .configurations()
+        .unwrap_or_else(|e| panic!("Couldn't create the configurations of the sweep ({e})"));
+
+    println!("Parameters");
+    for parameter in &sweep.parameters {
+        println!(
+            " - {}: {}",
+            parameter.name(),
+            parameter
+                .values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // Load all the images up front as they are analysed once per configuration
+    let targets: Vec<_> = discovery::discover(paths, &Selection::default())
+        .unwrap_or_else(|e| panic!("Failed to find the images to analyse ({e})"))
+        .into_par_iter()
+        .map(|target| {
+            let input = Input::open(&target.path)
+                .unwrap_or_else(|e| panic!("Could not load image {} ({e})", target.path.display()));
+            (target, input)
+        })
+        .collect();
+
+    println!(
+        "\nAnalysing {} images with {} configurations",
+        targets.len(),
+        configurations.len()
+    );
+
+    let rows = sweep::run(&configurations, cache, &targets);
+    sweep::write(Path::new("./output/sweep.csv"), &sweep.parameters, &rows)
+        .expect("Couldn't write the sweep results");
+
+    let failed = rows.iter().filter(|row| row.result.is_err()).count();
+    if failed > 0 {
+        eprintln!("Warning: {failed} of the analyses failed, see sweep.csv for the errors");
+    }
+
+    // Draw a heatmap for every pair of parameters taking several values
+    for i in 0..sweep.parameters.len() {
+        for j in i + 1..sweep.parameters.len() {
+            let (x, y) = (&sweep.parameters[i], &sweep.parameters[j]);
+            if x.values.len() < 2 || y.values.len() < 2 {
+                continue;
+            }
+
+            sweep::plot_heatmap(
+                &PathBuf::from(format!("./output/sweep_{}_{}.png", x.name(), y.name())),
+                x,
+                y,
+                (i, j),
+                &rows,
+            );
+        }
+    }
+
+    println!("\nThe results of every configuration and heatmaps of every pair of parameters have been exported to the 'output' directory");
 }
 
 /// The mean (average) of the input values
@@ -308,6 +734,11 @@ struct Args {
     /// The action to perform, runs in interactive mode on port 8080 if nothing is provided
     #[clap(subcommand)]
     action: Option<Action>,
+    /// The directory to cache the scale, pre-processed image and edge map of every analysed
+    /// image in, so they are only calculated again when the image or their parameters change.
+    /// Nothing is cached unless given, and the directory can be deleted at any time to clear it
+    #[clap(long, global = true, value_parser)]
+    cache: Option<PathBuf>,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -317,11 +748,14 @@ enum Action {
         /// The path to the configuration file to load (TOML)
         #[clap(short, long, value_parser)]
         config: Option<PathBuf>,
+        /// Only keep the listed intermediate images and tables (e.g. graphene.png,angles.csv)
+        #[clap(short, long, value_delimiter = ',')]
+        artifacts: Option<Vec<String>>,
         /// The path to the image to analyse
         #[clap(value_parser)]
         path: PathBuf,
     },
-    /// Analyse all images in a folder and aggregate the result
+    /// Analyse all images in folders (searched recursively) and aggregate the result
     Batch {
         /// The path to the configuration file to load (TOML)
         #[clap(short, long, value_parser)]
@@ -329,15 +763,84 @@ enum Action {
         /// Discard all images that error in some way
         #[clap(short, long)]
         discard_error: bool,
-        /// The path to the directory containing the images
+        /// Only keep the listed intermediate images and tables (e.g. graphene.png,angles.csv)
+        #[clap(short, long, value_delimiter = ',')]
+        artifacts: Option<Vec<String>>,
+        /// The format of the summary with one row per image
+        #[clap(long, value_enum, default_value_t)]
+        summary_format: SummaryFormat,
+        /// The extensions of the images to analyse within the directories (case insensitive)
+        #[clap(long, value_delimiter = ',', default_value = "tif,tiff,png,jpg,jpeg")]
+        extensions: Vec<String>,
+        /// Only analyse images with a path (relative to the directory) matching a glob pattern
+        #[clap(long)]
+        include: Vec<Pattern>,
+        /// Skip images with a path (relative to the directory) matching a glob pattern
+        #[clap(long)]
+        exclude: Vec<Pattern>,
+        /// Group the images by the directory containing them
+        #[clap(long, group = "grouping")]
+        group_by_directory: bool,
+        /// Group the images by a regular expression matched against the filename, using the
+        /// capture named `group` or otherwise all captures joined by '_'
+        #[clap(long, group = "grouping")]
+        group_pattern: Option<Regex>,
+        /// Group the images by a CSV file with `image` and `group` columns
+        #[clap(long, group = "grouping")]
+        group_manifest: Option<PathBuf>,
+        /// Skip the images already analysed successfully with the same configuration by an
+        /// earlier run of the batch, according to its run manifest (output/manifest.jsonl)
+        #[clap(long)]
+        resume: bool,
+        /// The directories containing the images or the images themselves, `-` reads a list of
+        /// images from stdin (one per line)
+        #[clap(value_parser, required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Find the exclusion parameters best correlating with measured bacterial viability or
+    /// coverage
+    Calibrate {
+        /// The path to the configuration file to load (TOML)
+        #[clap(short, long, value_parser)]
+        config: Option<PathBuf>,
+        /// The exclusion radii to try in micrometers, a list or a range (start:end:step)
+        #[clap(long, default_value = "0.5:2.0:0.1")]
+        radius: Values,
+        /// The contrast thresholds to try, a list or a range (start:end:step)
+        #[clap(long, default_value = "25:65:5")]
+        threshold: Values,
+        /// The correlation to maximise (in absolute value)
+        #[clap(long, value_enum, default_value_t)]
+        objective: Objective,
+        /// A CSV file with the `image` and its `measurement`, where images are relative to it
         #[clap(value_parser)]
-        path: PathBuf,
+        measurements: PathBuf,
+    },
+    /// Compare the groups of a batch with statistical tests and box plots
+    Compare {
+        /// The summary of a grouped batch (CSV, TSV or JSON)
+        #[clap(value_parser, default_value = "./output/summary.csv")]
+        summary: PathBuf,
+    },
+    /// Analyse images with every combination of the parameters given as lists or ranges
+    Sweep {
+        /// The path to the configuration file (TOML), where numeric fields can be lists
+        /// (`[30, 45, 60]`) or ranges (`"0.5:2.0:0.1"`)
+        #[clap(short, long, value_parser)]
+        config: PathBuf,
+        /// The images or directories containing them (searched recursively), `-` reads a list
+        /// of images from stdin (one per line)
+        #[clap(value_parser, required = true)]
+        paths: Vec<PathBuf>,
     },
     /// Start a web interface allowing for easy fine tuning of parameters
     Interactive {
         /// The address to serve the interface on
         #[clap(default_value = "127.0.0.1:8080")]
         address: SocketAddr,
+        /// The largest request body accepted, such as an uploaded image (MiB)
+        #[clap(long, default_value_t = DEFAULT_MAX_BODY)]
+        max_body: u64,
     },
     /// Exports the default configuration
     Export {
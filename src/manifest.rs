@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use crate::summary::{Status, SummaryRow};
+
+/// An image of a batch that has been analysed, as a line of the run manifest
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Entry {
+    /// The hash of the configuration the image was analysed with
+    config: String,
+    #[serde(flatten)]
+    row: SummaryRow,
+}
+
+/// Records the outcome of every image of a batch as soon as it is analysed, one JSON object per
+/// line, so the progress survives a crash
+#[derive(Debug)]
+pub struct Manifest {
+    file: Mutex<File>,
+    config: String,
+}
+
+impl Manifest {
+    /// Starts a new manifest for a batch with the configuration of the given hash, or continues
+    /// the existing one when resuming
+    pub fn open(path: &Path, config: String, resume: bool) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)?;
+
+        // End a line cut off by a crash so the next entry starts on a line of its own
+        if resume && fs::read(path)?.last().is_some_and(|byte| *byte != b'\n') {
+            file.write_all(b"\n")?;
+        }
+
+        Ok(Manifest {
+            file: Mutex::new(file),
+            config,
+        })
+    }
+
+    /// Appends the outcome of an image
+    pub fn record(&self, row: &SummaryRow) -> io::Result<()> {
+        let entry = Entry {
+            config: self.config.clone(),
+            row: row.clone(),
+        };
+
+        // Write the whole line at once so the lines of parallel analyses don't interleave
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+/// The images successfully analysed with the configuration of the given hash according to the
+/// manifest, by path. Lines that can't be read, such as one cut off by a crash, are skipped and
+/// later entries of an image replace earlier ones
+pub fn completed(path: &Path, config: &str) -> io::Result<BTreeMap<String, SummaryRow>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut rows = BTreeMap::new();
+    for entry in text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+    {
+        if entry.config != config {
+            continue;
+        }
+
+        if entry.row.status == Status::Ok {
+            rows.insert(entry.row.path.clone(), entry.row);
+        } else {
+            // A failure after a success means the image has to be analysed again
+            rows.remove(&entry.row.path);
+        }
+    }
+
+    Ok(rows)
+}
@@ -0,0 +1,330 @@
+use git_version::git_version;
+use serde::Serialize;
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    algorithms::{
+        self, bacteria_detection, determine_scale, exclusion_zone, graphene_angles, graphene_edges,
+        pre_processing, Detection, Edges, Exclusion, Flake, Scale, ScaleSource,
+    },
+    artifacts::{ArtifactError, ArtifactSink, NoSink},
+    cache::{self, Recorder, StageCache, StageOutput},
+    configuration::{Configuration, ThresholdSelection},
+    input::Input,
+};
+
+/// Runs all the enabled stages of the analysis on images using the same configuration
+#[derive(Debug, Clone, Copy)]
+pub struct Pipeline<'a> {
+    config: &'a Configuration,
+    /// Where the outputs of the expensive stages are reused from, if anywhere
+    cache: Option<&'a dyn StageCache>,
+}
+
+/// The outcome of analysing a single image
+#[derive(Debug, Clone)]
+pub struct AnalysisResult {
+    /// The scale of the image, from the metadata or the scale bar in the footer
+    pub scale: Scale,
+    /// The area within range of a graphene edge, if the stage is enabled
+    pub bacteria_exclusion: Option<Exclusion>,
+    /// The graphene flakes found in the image, if the stage is enabled
+    pub flakes: Option<Vec<Flake>>,
+    /// The bacteria found in the image, if the stage is enabled
+    pub bacteria: Option<Detection>,
+    /// The time spent in each stage
+    pub timings: Timings,
+    /// Problems with the analysis that didn't stop it, such as an unreliable scale
+    pub warnings: Vec<String>,
+}
+
+/// The time spent in each stage of the analysis, zero for disabled stages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub scale: Duration,
+    pub pre_processing: Duration,
+    pub bacteria_exclusion: Duration,
+    pub graphene_angles: Duration,
+    pub bacteria_detection: Duration,
+}
+
+impl Timings {
+    /// The time spent in all the stages combined
+    pub fn total(&self) -> Duration {
+        self.scale
+            + self.pre_processing
+            + self.bacteria_exclusion
+            + self.graphene_angles
+            + self.bacteria_detection
+    }
+}
+
+/// An error in one of the stages of the analysis
+#[derive(Debug)]
+pub enum AnalysisError {
+    Scale(algorithms::Error),
+    PreProcessing(ArtifactError),
+    BacteriaExclusion(Box<dyn std::error::Error + Send + Sync>),
+    GrapheneAngles(ArtifactError),
+    BacteriaDetection(ArtifactError),
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalysisError::Scale(e) => write!(f, "Failed to determine scale of image ({e})"),
+            AnalysisError::PreProcessing(e) => write!(f, "Failed to pre-process image ({e})"),
+            AnalysisError::BacteriaExclusion(e) => {
+                write!(f, "Failed to calculate bacteria exclusion ({e})")
+            }
+            AnalysisError::GrapheneAngles(e) => {
+                write!(f, "Failed to calculate graphene angles ({e})")
+            }
+            AnalysisError::BacteriaDetection(e) => write!(f, "Failed to detect bacteria ({e})"),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+/// A part of the configuration as JSON, for the cache keys
+fn serialized(value: &impl Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).expect("Failed to serialize the configuration")
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(config: &'a Configuration) -> Self {
+        Pipeline {
+            config,
+            cache: None,
+        }
+    }
+
+    /// Reuses the scale, pre-processed image and edge map of images analysed before with the
+    /// same configuration of those stages, and stores them for later runs
+    #[must_use]
+    pub fn with_cache(self, cache: &'a dyn StageCache) -> Self {
+        Pipeline {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// Runs a stage, or loads its output and re-emits its artifacts if it is in the cache with
+    /// every artifact the sink wants
+    fn cached<E: From<ArtifactError>>(
+        &self,
+        key: &str,
+        artifacts: &dyn ArtifactSink,
+        stage: impl FnOnce(&dyn ArtifactSink) -> Result<StageOutput, E>,
+    ) -> Result<StageOutput, E> {
+        let Some(cache) = self.cache else {
+            return stage(artifacts);
+        };
+
+        if let Some(entry) = cache.load(key) {
+            if entry.satisfies(artifacts) {
+                for (name, artifact) in entry.artifacts {
+                    if artifacts.accepts(&name) {
+                        artifacts.emit(&name, artifact)?;
+                    }
+                }
+
+                return Ok(entry.output);
+            }
+        }
+
+        let recorder = Recorder::new(artifacts);
+        let output = stage(&recorder)?;
+        cache.store(key, &recorder.into_entry(output.clone()));
+
+        Ok(output)
+    }
+
+    /// The configuration used for every image
+    pub fn config(&self) -> &'a Configuration {
+        self.config
+    }
+
+    /// Analyses a single image, which can be created from a `GrayImage` or loaded with its
+    /// metadata using [`Input::open`]. Intermediate images, plots and tables are emitted to the
+    /// artifact sink, use [`NoSink`](crate::artifacts::NoSink) to skip creating them
+    pub fn run(
+        &self,
+        input: Input,
+        artifacts: &dyn ArtifactSink,
+    ) -> Result<AnalysisResult, AnalysisError> {
+        let mut timings = Timings::default();
+
+        // The outputs of the stages are identified by the input and the configuration of the
+        // stage and the ones before it
+        let scale_key = cache::key(&[
+            b"scale",
+            git_version!().as_bytes(),
+            input.image.as_raw(),
+            &input.image.width().to_le_bytes(),
+            &input.image.height().to_le_bytes(),
+            format!("{:?}", input.pixel_size).as_bytes(),
+            format!("{:?}", input.magnification).as_bytes(),
+            &serialized(&self.config.text_recognition),
+        ]);
+        let pre_processing_key = cache::key(&[
+            b"pre_processing",
+            scale_key.as_bytes(),
+            &serialized(&self.config.pre_processing),
+        ]);
+        let edges_key = cache::key(&[
+            b"edges",
+            pre_processing_key.as_bytes(),
+            &serialized(&self.config.bacteria_exclusion.contrast_threshold),
+            &serialized(&self.config.bacteria_exclusion.minimum_edge_area),
+            &serialized(&self.config.bacteria_exclusion.edge_detector),
+            &serialized(&self.config.bacteria_exclusion.threshold_selection),
+        ]);
+
+        // Determine scale (um / px)
+        let start = Instant::now();
+        let output = self
+            .cached(&scale_key, artifacts, |artifacts| {
+                determine_scale(
+                    input.image,
+                    &self.config.text_recognition,
+                    input.pixel_size,
+                    input.magnification,
+                    artifacts,
+                )
+                .map(|(scale, image)| StageOutput {
+                    scale: Some(scale),
+                    ..StageOutput::new(image)
+                })
+            })
+            .map_err(AnalysisError::Scale)?;
+        let scale = output
+            .scale
+            .expect("The scale stage always stores the scale");
+        timings.scale = start.elapsed();
+
+        let mut warnings = Vec::new();
+        if scale.confidence < self.config.text_recognition.minimum_confidence {
+            warnings.push(if scale.source == ScaleSource::Override {
+                format!(
+                    "The confidence in the detected scale is low ({:.2}), the override was used instead",
+                    scale.confidence
+                )
+            } else {
+                format!(
+                    "The confidence in the detected scale is low ({:.2})",
+                    scale.confidence
+                )
+            });
+        }
+
+        let exclusion = &self.config.bacteria_exclusion;
+        if exclusion.enabled
+            && exclusion.threshold_selection != ThresholdSelection::Fixed
+            && !exclusion.selects_threshold()
+        {
+            warnings.push(format!(
+                "The threshold selection is ignored by the {} edge detector, which has its own thresholds",
+                exclusion.edge_detector.name()
+            ));
+        }
+
+        // Preprocessing
+        let start = Instant::now();
+        let image = self
+            .cached(&pre_processing_key, artifacts, |_| {
+                Ok::<_, ArtifactError>(StageOutput::new(pre_processing(
+                    output.image,
+                    self.config.pre_processing,
+                )))
+            })
+            .map_err(AnalysisError::PreProcessing)?
+            .image;
+        timings.pre_processing = start.elapsed();
+
+        // Find angles of graphene in the image
+        let flakes = if self.config.graphene_angles.enabled {
+            let start = Instant::now();
+            let flakes =
+                graphene_angles(&image, &self.config.graphene_angles, scale.scale, artifacts)
+                    .map_err(AnalysisError::GrapheneAngles)?;
+            timings.graphene_angles = start.elapsed();
+
+            Some(flakes)
+        } else {
+            None
+        };
+
+        // Find graphene and determine bacteria exclusion percentage
+        let bacteria_exclusion = if self.config.bacteria_exclusion.enabled {
+            let start = Instant::now();
+            let output = self
+                .cached(&edges_key, artifacts, |artifacts| {
+                    graphene_edges(&image, &self.config.bacteria_exclusion, artifacts).map(
+                        |edges| StageOutput {
+                            contrast_threshold: edges.contrast_threshold,
+                            ..StageOutput::new(edges.mask)
+                        },
+                    )
+                })
+                .map_err(|e| AnalysisError::BacteriaExclusion(Box::new(e)))?;
+            let edges = Edges {
+                mask: output.image,
+                contrast_threshold: output.contrast_threshold,
+            };
+            // The directional exclusion can use the flakes even if they aren't analysed
+            let unreported_flakes = match flakes {
+                None if self.config.bacteria_exclusion.exclusion_mode.uses_flakes() => {
+                    graphene_angles(&image, &self.config.graphene_angles, scale.scale, &NoSink)
+                        .map_err(|e| AnalysisError::BacteriaExclusion(Box::new(e)))?
+                }
+                _ => Vec::new(),
+            };
+            let exclusion = exclusion_zone(
+                &image,
+                &edges,
+                flakes.as_deref().unwrap_or(&unreported_flakes),
+                &self.config.bacteria_exclusion,
+                scale.scale,
+                artifacts,
+            )
+            .map_err(AnalysisError::BacteriaExclusion)?;
+            timings.bacteria_exclusion = start.elapsed();
+
+            Some(exclusion)
+        } else {
+            None
+        };
+
+        // Find the bacteria on the surface
+        let bacteria = if self.config.bacteria_detection.enabled {
+            let start = Instant::now();
+            let bacteria = bacteria_detection(
+                &image,
+                &self.config.bacteria_detection,
+                scale.scale,
+                artifacts,
+            )
+            .map_err(AnalysisError::BacteriaDetection)?;
+            timings.bacteria_detection = start.elapsed();
+
+            Some(bacteria)
+        } else {
+            None
+        };
+
+        Ok(AnalysisResult {
+            scale,
+            bacteria_exclusion,
+            flakes,
+            bacteria,
+            timings,
+            warnings,
+        })
+    }
+}
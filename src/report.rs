@@ -0,0 +1,82 @@
+use git_version::git_version;
+use serde::Serialize;
+
+use crate::{
+    algorithms::{Detection, FlakeStatistics, Scale},
+    configuration::Configuration,
+    pipeline::AnalysisResult,
+};
+
+/// The machine-readable outcome of analysing an image, written as `results.json` next to the
+/// intermediate images
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// The version of the program that made the analysis
+    pub program_version: String,
+    /// The hash of the configuration used, see [`Configuration::hash`]
+    pub config_hash: String,
+    /// The path or name of the analysed image
+    pub image: String,
+    /// The scale of the image and where it came from
+    pub scale: Scale,
+    /// The ratio of the entire image within range of a graphene edge
+    pub exclusion_ratio: Option<f32>,
+    /// The exclusion ratio estimated from a radial sample, if enabled
+    pub radius_adjusted_ratio: Option<f32>,
+    /// The threshold on the edge strength the edges were found with by the contrast or gradient
+    /// detector, chosen for the image unless it is the fixed contrast threshold
+    pub contrast_threshold: Option<f32>,
+    /// The fraction of the positions and orientations where a bacterium fits, for the bacterium
+    /// exclusion mode
+    pub placeable_ratio: Option<f32>,
+    /// The number of flakes found, if the graphene angles are calculated
+    pub flake_count: Option<usize>,
+    /// The length and angle statistics of the flakes, if there are any
+    pub flake_statistics: Option<FlakeStatistics>,
+    /// The number of bacteria found, if the bacteria are detected
+    pub bacteria_count: Option<usize>,
+    /// The number of bacteria per square millimeter
+    pub bacteria_density: Option<f32>,
+    /// The ratio of the image covered by bacteria
+    pub bacteria_coverage: Option<f32>,
+    /// The ratio of the image covered by regions that weren't accepted as bacteria
+    pub bacteria_rejected_coverage: Option<f32>,
+    /// Problems with the analysis that didn't stop it
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    pub fn new(image: impl Into<String>, config: &Configuration, result: &AnalysisResult) -> Self {
+        Report {
+            program_version: git_version!().to_string(),
+            config_hash: config.hash(),
+            image: image.into(),
+            scale: result.scale,
+            exclusion_ratio: result.bacteria_exclusion.map(|exclusion| exclusion.ratio),
+            radius_adjusted_ratio: result
+                .bacteria_exclusion
+                .and_then(|exclusion| exclusion.radius_adjusted_ratio),
+            contrast_threshold: result
+                .bacteria_exclusion
+                .and_then(|exclusion| exclusion.contrast_threshold),
+            placeable_ratio: result
+                .bacteria_exclusion
+                .and_then(|exclusion| exclusion.placeable_ratio),
+            flake_count: result.flakes.as_ref().map(Vec::len),
+            flake_statistics: result.flakes.as_deref().and_then(FlakeStatistics::new),
+            bacteria_count: result.bacteria.as_ref().map(Detection::count),
+            bacteria_density: result.bacteria.as_ref().map(|detection| detection.density),
+            bacteria_coverage: result.bacteria.as_ref().map(|detection| detection.coverage),
+            bacteria_rejected_coverage: result
+                .bacteria
+                .as_ref()
+                .map(|detection| detection.rejected_coverage),
+            warnings: result.warnings.clone(),
+        }
+    }
+
+    /// The report as pretty printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the report")
+    }
+}
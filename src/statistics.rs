@@ -0,0 +1,478 @@
+use statrs::{
+    distribution::{Continuous, ContinuousCDF, FisherSnedecor, Normal, StudentsT},
+    function::gamma::ln_gamma,
+};
+
+/// The outcome of a statistical test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TestResult {
+    /// The test statistic (t, U, F or q)
+    pub statistic: f64,
+    /// The degrees of freedom of the distribution of the statistic, if it has any
+    pub degrees_of_freedom: (Option<f64>, Option<f64>),
+    pub p_value: f64,
+    pub effect_size: f64,
+}
+
+/// The mean and sample variance of the values
+fn mean_variance(values: &[f64]) -> (f64, f64) {
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0);
+
+    (mean, variance)
+}
+
+/// Welch's t-test of the means of two samples with possibly different variances. The effect
+/// size is Cohen's d using the pooled standard deviation
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<TestResult> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let (mean_a, variance_a) = mean_variance(a);
+    let (mean_b, variance_b) = mean_variance(b);
+
+    let error_a = variance_a / n_a;
+    let error_b = variance_b / n_b;
+    let standard_error = (error_a + error_b).sqrt();
+    if standard_error == 0.0 {
+        return None;
+    }
+
+    let t = (mean_a - mean_b) / standard_error;
+    // The Welch–Satterthwaite approximation of the degrees of freedom
+    let degrees_of_freedom = (error_a + error_b).powi(2)
+        / (error_a.powi(2) / (n_a - 1.0) + error_b.powi(2) / (n_b - 1.0));
+    let distribution = StudentsT::new(0.0, 1.0, degrees_of_freedom).ok()?;
+
+    let pooled_deviation =
+        (((n_a - 1.0) * variance_a + (n_b - 1.0) * variance_b) / (n_a + n_b - 2.0)).sqrt();
+
+    Some(TestResult {
+        statistic: t,
+        degrees_of_freedom: (Some(degrees_of_freedom), None),
+        p_value: 2.0 * (1.0 - distribution.cdf(t.abs())),
+        effect_size: (mean_a - mean_b) / pooled_deviation,
+    })
+}
+
+/// The ranks of the values (starting at 1), where tied values get the mean of their ranks. Also
+/// returns the sum of `t^3 - t` over the groups of `t` tied values
+fn ranks(values: &[f64]) -> (Vec<f64>, f64) {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&i, &j| values[i].total_cmp(&values[j]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut tie_correction = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]].total_cmp(&values[order[start]]).is_eq() {
+            end += 1;
+        }
+
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+
+        let tied = (end - start) as f64;
+        tie_correction += tied.powi(3) - tied;
+        start = end;
+    }
+
+    (ranks, tie_correction)
+}
+
+/// The Mann-Whitney U test of whether one sample tends to be larger than the other, using the
+/// normal approximation with tie and continuity correction. The statistic is the U of the first
+/// sample and the effect size is the rank-biserial correlation
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> Option<TestResult> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let total = n_a + n_b;
+    let (ranks, tie_correction) = ranks(&[a, b].concat());
+
+    let rank_sum: f64 = ranks[..a.len()].iter().sum();
+    let statistic = rank_sum - n_a * (n_a + 1.0) / 2.0;
+
+    let mean = n_a * n_b / 2.0;
+    let deviation =
+        (n_a * n_b / 12.0 * ((total + 1.0) - tie_correction / (total * (total - 1.0)))).sqrt();
+    if deviation == 0.0 {
+        return None;
+    }
+
+    let z_score = ((statistic - mean).abs() - 0.5).max(0.0) / deviation;
+    let normal = Normal::new(0.0, 1.0).unwrap();
+
+    Some(TestResult {
+        statistic,
+        degrees_of_freedom: (None, None),
+        p_value: 2.0 * (1.0 - normal.cdf(z_score)),
+        effect_size: 2.0 * statistic / (n_a * n_b) - 1.0,
+    })
+}
+
+/// The one-way analysis of variance of whether the means of all the samples are the same. The
+/// effect size is eta squared, the share of the variance explained by the samples
+pub fn one_way_anova(samples: &[&[f64]]) -> Option<TestResult> {
+    let groups = samples.len() as f64;
+    let count = samples.iter().map(|sample| sample.len()).sum::<usize>() as f64;
+    if samples.len() < 2 || samples.iter().any(|sample| sample.is_empty()) || count <= groups {
+        return None;
+    }
+
+    let mean = samples.iter().flat_map(|sample| sample.iter()).sum::<f64>() / count;
+
+    let mut between = 0.0;
+    let mut within = 0.0;
+    for sample in samples {
+        let sample_mean = sample.iter().sum::<f64>() / sample.len() as f64;
+        between += sample.len() as f64 * (sample_mean - mean).powi(2);
+        within += sample
+            .iter()
+            .map(|x| (x - sample_mean).powi(2))
+            .sum::<f64>();
+    }
+
+    if within == 0.0 {
+        return None;
+    }
+
+    let f = (between / (groups - 1.0)) / (within / (count - groups));
+    let distribution = FisherSnedecor::new(groups - 1.0, count - groups).ok()?;
+
+    Some(TestResult {
+        statistic: f,
+        degrees_of_freedom: (Some(groups - 1.0), Some(count - groups)),
+        p_value: 1.0 - distribution.cdf(f),
+        effect_size: between / (between + within),
+    })
+}
+
+/// The length of the sum of the angles in radians as unit vectors
+fn resultant_length<'a>(angles: impl Iterator<Item = &'a f64>) -> f64 {
+    let (sin, cos) = angles.fold((0.0, 0.0), |(sin, cos), angle| {
+        (sin + angle.sin(), cos + angle.cos())
+    });
+
+    cos.hypot(sin)
+}
+
+/// The Watson-Williams test of whether the mean directions of samples of angles in radians are
+/// the same, the circular analogue of the one-way ANOVA. It assumes von Mises distributions with
+/// the same concentration, which is estimated from the mean resultant length within the samples.
+/// The effect size is the share of the circular dispersion explained by the samples
+pub fn watson_williams(samples: &[&[f64]]) -> Option<TestResult> {
+    let groups = samples.len() as f64;
+    let count = samples.iter().map(|sample| sample.len()).sum::<usize>() as f64;
+    if samples.len() < 2 || samples.iter().any(|sample| sample.is_empty()) || count <= groups {
+        return None;
+    }
+
+    let within: f64 = samples
+        .iter()
+        .map(|sample| resultant_length(sample.iter()))
+        .sum();
+    let total = resultant_length(samples.iter().flat_map(|sample| sample.iter()));
+    if count - within < 1e-12 {
+        return None;
+    }
+
+    // The approximate maximum likelihood estimate of the concentration and the correction of the
+    // statistic for it (Mardia & Jupp, 2000)
+    let mean_length = within / count;
+    let concentration = if mean_length < 0.53 {
+        2.0 * mean_length + mean_length.powi(3) + 5.0 * mean_length.powi(5) / 6.0
+    } else if mean_length < 0.85 {
+        -0.4 + 1.39 * mean_length + 0.43 / (1.0 - mean_length)
+    } else {
+        1.0 / (mean_length.powi(3) - 4.0 * mean_length.powi(2) + 3.0 * mean_length)
+    };
+    let correction = 1.0 + 3.0 / (8.0 * concentration);
+
+    let f = correction * (count - groups) * (within - total) / ((groups - 1.0) * (count - within));
+    let distribution = FisherSnedecor::new(groups - 1.0, count - groups).ok()?;
+
+    Some(TestResult {
+        statistic: f,
+        degrees_of_freedom: (Some(groups - 1.0), Some(count - groups)),
+        p_value: 1.0 - distribution.cdf(f),
+        effect_size: (within - total) / (count - total),
+    })
+}
+
+/// Tukey's honestly significant difference test (the Tukey-Kramer variant for unequal sizes)
+/// between every pair of samples, following an ANOVA. Returns the indices of the pair and the
+/// test with the studentized range q, where the effect size is the difference of the means in
+/// standard deviations within the samples
+pub fn tukey_hsd(samples: &[&[f64]]) -> Vec<(usize, usize, TestResult)> {
+    let groups = samples.len() as f64;
+    let count = samples.iter().map(|sample| sample.len()).sum::<usize>() as f64;
+    if samples.len() < 2 || samples.iter().any(|sample| sample.is_empty()) || count <= groups {
+        return Vec::new();
+    }
+
+    let means: Vec<f64> = samples
+        .iter()
+        .map(|sample| sample.iter().sum::<f64>() / sample.len() as f64)
+        .collect();
+    let within = samples
+        .iter()
+        .zip(&means)
+        .map(|(sample, mean)| sample.iter().map(|x| (x - mean).powi(2)).sum::<f64>())
+        .sum::<f64>();
+    let degrees_of_freedom = count - groups;
+    let mean_square = within / degrees_of_freedom;
+    if mean_square == 0.0 {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    for i in 0..samples.len() {
+        for j in i + 1..samples.len() {
+            let difference = means[i] - means[j];
+            let standard_error = (mean_square / 2.0
+                * (1.0 / samples[i].len() as f64 + 1.0 / samples[j].len() as f64))
+                .sqrt();
+            let q = difference.abs() / standard_error;
+
+            results.push((
+                i,
+                j,
+                TestResult {
+                    statistic: q,
+                    degrees_of_freedom: (Some(groups), Some(degrees_of_freedom)),
+                    p_value: (1.0 - studentized_range_cdf(q, groups, degrees_of_freedom))
+                        .clamp(0.0, 1.0),
+                    effect_size: difference / mean_square.sqrt(),
+                },
+            ));
+        }
+    }
+
+    results
+}
+
+/// Integrates the function over the range with Simpson's rule using an even number of intervals
+fn simpson(f: impl Fn(f64) -> f64, start: f64, end: f64, intervals: usize) -> f64 {
+    let step = (end - start) / intervals as f64;
+    let inner: f64 = (1..intervals)
+        .map(|i| {
+            let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+            weight * f(start + i as f64 * step)
+        })
+        .sum();
+
+    (f(start) + inner + f(end)) * step / 3.0
+}
+
+/// The probability that the range of `groups` standard normal values is below `w`
+fn normal_range_cdf(w: f64, groups: f64) -> f64 {
+    let normal = Normal::new(0.0, 1.0).unwrap();
+
+    groups
+        * simpson(
+            |z| normal.pdf(z) * (normal.cdf(z) - normal.cdf(z - w)).powf(groups - 1.0),
+            -8.0,
+            8.0,
+            200,
+        )
+}
+
+/// The cumulative distribution function of the studentized range of `groups` means with the
+/// given degrees of freedom of the variance estimate
+pub fn studentized_range_cdf(q: f64, groups: f64, degrees_of_freedom: f64) -> f64 {
+    if q <= 0.0 {
+        return 0.0;
+    }
+
+    // The variance estimate is practically exact for large samples
+    if degrees_of_freedom > 25_000.0 {
+        return normal_range_cdf(q, groups);
+    }
+
+    // Integrates over the distribution of the estimated standard deviation (relative to the
+    // true one), a chi distribution scaled by 1 / sqrt(df)
+    let half = degrees_of_freedom / 2.0;
+    let log_constant =
+        half * degrees_of_freedom.ln() - ln_gamma(half) - (half - 1.0) * 2.0_f64.ln();
+    let density = |s: f64| {
+        if s <= 0.0 {
+            return 0.0;
+        }
+
+        (log_constant + (degrees_of_freedom - 1.0) * s.ln() - half * s * s).exp()
+    };
+
+    let spread = 10.0 / degrees_of_freedom.sqrt();
+    simpson(
+        |s| density(s) * normal_range_cdf(q * s, groups),
+        (1.0 - spread).max(0.0),
+        1.0 + spread,
+        300,
+    )
+}
+
+/// The Pearson correlation coefficient of paired values, if neither is constant
+pub fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    if x.len() != y.len() || x.len() < 2 {
+        return None;
+    }
+
+    let count = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / count;
+    let mean_y = y.iter().sum::<f64>() / count;
+
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for (a, b) in x.iter().zip(y) {
+        covariance += (a - mean_x) * (b - mean_y);
+        variance_x += (a - mean_x).powi(2);
+        variance_y += (b - mean_y).powi(2);
+    }
+
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+
+    Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+/// The Spearman rank correlation coefficient of paired values, the Pearson correlation of their
+/// ranks
+pub fn spearman(x: &[f64], y: &[f64]) -> Option<f64> {
+    pearson(&ranks(x).0, &ranks(y).0)
+}
+
+/// The least squares fit of `y = slope * x + intercept`, returned as `(slope, intercept)`
+pub fn linear_fit(x: &[f64], y: &[f64]) -> Option<(f64, f64)> {
+    if x.len() != y.len() || x.len() < 2 {
+        return None;
+    }
+
+    let count = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / count;
+    let mean_y = y.iter().sum::<f64>() / count;
+
+    let covariance: f64 = x
+        .iter()
+        .zip(y)
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum();
+    let variance: f64 = x.iter().map(|a| (a - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / variance;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        linear_fit, mann_whitney_u, one_way_anova, pearson, spearman, studentized_range_cdf,
+        tukey_hsd, watson_williams, welch_t_test,
+    };
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn welch_t_test_matches_reference() {
+        let result = welch_t_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 4.0, 6.0, 8.0, 10.0]).unwrap();
+
+        assert_close(result.statistic, -1.8974, 1e-4);
+        assert_close(result.degrees_of_freedom.0.unwrap(), 5.8824, 1e-4);
+        assert_close(result.p_value, 0.1075, 1e-3);
+    }
+
+    #[test]
+    fn mann_whitney_u_matches_reference() {
+        let result =
+            mann_whitney_u(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]).unwrap();
+
+        assert_close(result.statistic, 0.0, 1e-9);
+        assert_close(result.p_value, 0.01219, 1e-4);
+        assert_close(result.effect_size, -1.0, 1e-9);
+    }
+
+    #[test]
+    fn anova_matches_reference() {
+        let samples: [&[f64]; 3] = [&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0], &[7.0, 8.0, 9.0]];
+        let result = one_way_anova(&samples).unwrap();
+
+        assert_close(result.statistic, 27.0, 1e-9);
+        assert_close(result.p_value, 0.001, 1e-4);
+    }
+
+    #[test]
+    fn watson_williams_matches_reference() {
+        // Example 27.7 of Zar, Biostatistical Analysis (4th edition), in degrees
+        let a = [
+            94.0, 65.0, 45.0, 52.0, 38.0, 47.0, 73.0, 82.0, 90.0, 40.0, 87.0,
+        ];
+        let b = [77.0, 70.0, 61.0, 45.0, 50.0, 35.0, 48.0, 65.0, 36.0];
+        let (a, b) = (a.map(f64::to_radians), b.map(f64::to_radians));
+        let result = watson_williams(&[&a, &b]).unwrap();
+
+        assert_close(result.statistic, 1.61, 1e-2);
+        assert_eq!(result.degrees_of_freedom, (Some(1.0), Some(18.0)));
+        assert_close(result.p_value, 0.22, 1e-2);
+    }
+
+    #[test]
+    fn watson_williams_wraps_around() {
+        // Means either side of 0 and 2π are the same direction, opposite ones are not
+        let a = [0.1, 0.2, -0.1, 0.0];
+        let b = [6.2, 0.05, 6.25, 0.15];
+        let c = [3.1, 3.2, 3.0, 3.15];
+
+        assert!(watson_williams(&[&a, &b]).unwrap().p_value > 0.5);
+        assert!(watson_williams(&[&a, &c]).unwrap().p_value < 1e-3);
+    }
+
+    #[test]
+    fn studentized_range_matches_tables() {
+        // The 95% critical values of q for 3 groups with 10 degrees of freedom and 5 groups
+        // with 20 degrees of freedom
+        assert_close(studentized_range_cdf(3.877, 3.0, 10.0), 0.95, 1e-3);
+        assert_close(studentized_range_cdf(4.232, 5.0, 20.0), 0.95, 1e-3);
+    }
+
+    #[test]
+    fn tukey_hsd_compares_every_pair() {
+        let samples: [&[f64]; 3] = [&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0], &[7.0, 8.0, 9.0]];
+        let results = tukey_hsd(&samples);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!((results[0].0, results[0].1), (0, 1));
+        // The adjusted p-values for the adjacent and the outer groups
+        assert_close(results[0].2.statistic, 5.1962, 1e-4);
+        assert_close(results[0].2.p_value, 0.0243, 1e-3);
+        assert_close(results[1].2.p_value, 0.0008, 5e-4);
+    }
+
+    #[test]
+    fn correlations_match_reference() {
+        let x = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let y = [2.0, 1.0, 4.0, 3.0, 7.0];
+
+        assert_close(pearson(&x, &y).unwrap(), 0.8242, 1e-4);
+        assert_close(spearman(&x, &y).unwrap(), 0.8, 1e-9);
+        assert_close(linear_fit(&x, &y).unwrap().0, 1.2, 1e-9);
+        assert_close(linear_fit(&x, &y).unwrap().1, -0.2, 1e-9);
+    }
+}
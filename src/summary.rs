@@ -0,0 +1,167 @@
+use graphene_analysis::Report;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The file formats the batch summary can be written in
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum SummaryFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl SummaryFormat {
+    /// The path of the summary in the given directory
+    pub fn path(self, directory: impl AsRef<Path>) -> PathBuf {
+        directory.as_ref().join(match self {
+            SummaryFormat::Csv => "summary.csv",
+            SummaryFormat::Tsv => "summary.tsv",
+            SummaryFormat::Json => "summary.json",
+        })
+    }
+}
+
+/// Whether the analysis of an image succeeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+/// The outcome of analysing one of the images in a batch
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SummaryRow {
+    pub path: String,
+    /// The sample or condition of the image, if the batch is grouped
+    pub group: Option<String>,
+    pub status: Status,
+    pub error: Option<String>,
+    /// The size of a pixel (um / px)
+    pub scale: Option<f32>,
+    /// The length of the scale bar in micrometers
+    pub micrometers: Option<f32>,
+    /// The length of the scale bar in pixels
+    pub pixels: Option<u32>,
+    /// The radius adjusted exclusion ratio if enabled, otherwise the ratio of the entire image
+    pub exclusion_ratio: Option<f32>,
+    pub flake_count: Option<usize>,
+    /// The mean length of the flakes in micrometers
+    pub mean_length: Option<f32>,
+    /// The mean angle of the flakes in radians
+    pub mean_angle: Option<f32>,
+    pub order_parameter: Option<f32>,
+    pub bacteria_count: Option<usize>,
+    /// The number of bacteria per square millimeter
+    pub bacteria_density: Option<f32>,
+    /// The ratio of the image covered by bacteria
+    pub bacteria_coverage: Option<f32>,
+    /// The time spent loading and analysing the image in seconds
+    pub run_time: f64,
+}
+
+impl SummaryRow {
+    /// A row for a successfully analysed image
+    pub fn new(report: &Report, run_time: Duration) -> Self {
+        SummaryRow {
+            path: report.image.clone(),
+            group: None,
+            status: Status::Ok,
+            error: None,
+            scale: Some(report.scale.scale),
+            micrometers: Some(report.scale.micrometers),
+            pixels: Some(report.scale.pixels),
+            exclusion_ratio: report.radius_adjusted_ratio.or(report.exclusion_ratio),
+            flake_count: report.flake_count,
+            mean_length: report
+                .flake_statistics
+                .map(|statistics| statistics.mean_length),
+            mean_angle: report
+                .flake_statistics
+                .map(|statistics| statistics.mean_angle),
+            order_parameter: report
+                .flake_statistics
+                .map(|statistics| statistics.order_parameter),
+            bacteria_count: report.bacteria_count,
+            bacteria_density: report.bacteria_density,
+            bacteria_coverage: report.bacteria_coverage,
+            run_time: run_time.as_secs_f64(),
+        }
+    }
+
+    /// A row for an image that couldn't be analysed
+    pub fn failed(path: &Path, error: String, run_time: Duration) -> Self {
+        SummaryRow {
+            path: path.display().to_string(),
+            group: None,
+            status: Status::Error,
+            error: Some(error),
+            scale: None,
+            micrometers: None,
+            pixels: None,
+            exclusion_ratio: None,
+            flake_count: None,
+            mean_length: None,
+            mean_angle: None,
+            order_parameter: None,
+            bacteria_count: None,
+            bacteria_density: None,
+            bacteria_coverage: None,
+            run_time: run_time.as_secs_f64(),
+        }
+    }
+}
+
+/// Writes the summary with one row per image, in the order given
+pub fn write(path: &Path, rows: &[SummaryRow], format: SummaryFormat) -> io::Result<()> {
+    match format {
+        SummaryFormat::Csv | SummaryFormat::Tsv => {
+            let delimiter = if let SummaryFormat::Tsv = format {
+                b'\t'
+            } else {
+                b','
+            };
+
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_path(path)?;
+
+            for row in rows {
+                writer.serialize(row)?;
+            }
+
+            writer.flush()
+        }
+        SummaryFormat::Json => fs::write(path, serde_json::to_string_pretty(rows)?),
+    }
+}
+
+/// Reads a summary written by [`write`], in the format given by the extension of the path
+/// (CSV unless it is `.tsv` or `.json`)
+pub fn read(path: &Path) -> io::Result<Vec<SummaryRow>> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("json") => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+        extension => {
+            let delimiter = if extension == Some("tsv") {
+                b'\t'
+            } else {
+                b','
+            };
+
+            let rows = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .from_path(path)?
+                .deserialize()
+                .collect::<csv::Result<_>>()?;
+
+            Ok(rows)
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use graphene_analysis::{artifacts::NoSink, cache::StageCache, Configuration, Input, Report};
+use plotters::prelude::{
+    BitMapBackend, ChartBuilder, Color, IntoDrawingArea, IntoFont, IntoSegmentedCoord, Rectangle,
+    SegmentValue, Text, ViridisRGB, BLACK, WHITE,
+};
+use rayon::prelude::*;
+use toml::Value;
+
+use std::{collections::BTreeMap, io, path::Path, str::FromStr};
+
+use crate::{calibrate::Values, discovery::Target, pipeline};
+
+/// The size of the heatmaps in pixels
+const PLOT_WIDTH: u32 = 640;
+const PLOT_HEIGHT: u32 = 480;
+
+/// The largest number of values along an axis of a heatmap where the cells are labelled
+const LABELLED_CELLS: usize = 12;
+
+/// A field of the configuration taking several values in a sweep
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    /// The path to the field through the tables of the configuration
+    pub path: Vec<String>,
+    pub values: Vec<Value>,
+}
+
+impl Parameter {
+    /// The dotted path of the field, e.g. `bacteria_exclusion.contrast_threshold`
+    pub fn name(&self) -> String {
+        self.path.join(".")
+    }
+}
+
+/// A configuration where any numeric field can be a list (`[30, 45, 60]`) or a range
+/// (`"0.5:2.0:0.1"`) of values to try, instead of a single value
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    base: Value,
+    pub parameters: Vec<Parameter>,
+}
+
+/// The values of a range string such as `"30:60:5"`, integers if all parts are integers
+fn parse_range(text: &str) -> Option<Vec<Value>> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    if let [Ok(start), Ok(end), Ok(step)] = [0, 1, 2].map(|i| parts[i].trim().parse::<i64>()) {
+        if step <= 0 || end < start {
+            return None;
+        }
+
+        return Some(
+            (start..=end)
+                .step_by(step as usize)
+                .map(Value::Integer)
+                .collect(),
+        );
+    }
+
+    Values::from_str(text).ok().map(|values| {
+        values
+            .0
+            .into_iter()
+            .map(|value| Value::Float(f64::from(value)))
+            .collect()
+    })
+}
+
+/// Finds the fields with several values in the tables, in alphabetical order
+fn find_parameters(value: &Value, path: &mut Vec<String>, parameters: &mut Vec<Parameter>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                path.push(key.clone());
+                find_parameters(value, path, parameters);
+                path.pop();
+            }
+        }
+        Value::Array(values)
+            if !values.is_empty()
+                && values
+                    .iter()
+                    .all(|value| matches!(value, Value::Integer(_) | Value::Float(_))) =>
+        {
+            parameters.push(Parameter {
+                path: path.clone(),
+                values: values.clone(),
+            });
+        }
+        Value::String(text) => {
+            if let Some(values) = parse_range(text) {
+                parameters.push(Parameter {
+                    path: path.clone(),
+                    values,
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Sweep {
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        let base: Value = toml::from_str(text)?;
+
+        let mut parameters = Vec::new();
+        find_parameters(&base, &mut Vec::new(), &mut parameters);
+
+        Ok(Sweep { base, parameters })
+    }
+
+    /// The version of the program the configuration was made by, if it is given
+    pub fn program_version(&self) -> Option<&str> {
+        self.base.get("program_version").and_then(Value::as_str)
+    }
+
+    /// Every combination of the parameter values (the cartesian product), as the index of the
+    /// value of each parameter and the resulting configuration
+    pub fn configurations(&self) -> Result<Vec<(Vec<usize>, Configuration)>, toml::de::Error> {
+        let count: usize = self
+            .parameters
+            .iter()
+            .map(|parameter| parameter.values.len())
+            .product();
+
+        (0..count)
+            .map(|mut combination| {
+                let mut indices = vec![0; self.parameters.len()];
+                let mut config = self.base.clone();
+
+                // The last parameter varies the fastest
+                for (i, parameter) in self.parameters.iter().enumerate().rev() {
+                    indices[i] = combination % parameter.values.len();
+                    combination /= parameter.values.len();
+
+                    let (last, tables) = parameter.path.split_last().unwrap();
+                    let mut table = &mut config;
+                    for key in tables {
+                        table = table.get_mut(key).unwrap();
+                    }
+
+                    table
+                        .as_table_mut()
+                        .unwrap()
+                        .insert(last.clone(), parameter.values[indices[i]].clone());
+                }
+
+                Ok((indices, config.try_into()?))
+            })
+            .collect()
+    }
+}
+
+/// The outcome of analysing an image with one combination of the parameters
+#[derive(Debug, Clone)]
+pub struct SweepRow {
+    pub image: String,
+    /// The index of the value of each parameter
+    pub indices: Vec<usize>,
+    pub result: Result<Report, String>,
+}
+
+/// Analyses every image with every combination of the parameters in parallel, in the order of
+/// the configurations and then the images. The scale and pre-processing are shared through the
+/// cache by the configurations only differing in later stages
+pub fn run(
+    configurations: &[(Vec<usize>, Configuration)],
+    cache: Option<&dyn StageCache>,
+    targets: &[(Target, Input)],
+) -> Vec<SweepRow> {
+    let jobs: Vec<_> = configurations
+        .iter()
+        .flat_map(|configuration| targets.iter().map(move |target| (configuration, target)))
+        .collect();
+
+    jobs.par_iter()
+        .map(|((indices, config), (target, input))| {
+            let image = target.path.display().to_string();
+            let result = pipeline(config, cache)
+                .run(input.clone(), &NoSink)
+                .map(|result| Report::new(image.clone(), config, &result))
+                .map_err(|e| e.to_string());
+
+            SweepRow {
+                image,
+                indices: indices.clone(),
+                result,
+            }
+        })
+        .collect()
+}
+
+/// Writes the results as a tidy CSV with one row per image and combination of parameters, with
+/// a column for each parameter
+pub fn write(path: &Path, parameters: &[Parameter], rows: &[SweepRow]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    let mut header = vec!["image".to_string()];
+    header.extend(parameters.iter().map(Parameter::name));
+    header.extend(
+        [
+            "status",
+            "error",
+            "exclusion_ratio",
+            "flake_count",
+            "mean_length",
+            "order_parameter",
+        ]
+        .map(String::from),
+    );
+    writer.write_record(&header)?;
+
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    for row in rows {
+        let mut record = vec![row.image.clone()];
+        record.extend(
+            parameters
+                .iter()
+                .zip(&row.indices)
+                .map(|(parameter, index)| parameter.values[*index].to_string()),
+        );
+
+        match &row.result {
+            Ok(report) => record.extend([
+                "ok".to_string(),
+                String::new(),
+                optional(
+                    report
+                        .radius_adjusted_ratio
+                        .or(report.exclusion_ratio)
+                        .map(|ratio| ratio.to_string()),
+                ),
+                optional(report.flake_count.map(|count| count.to_string())),
+                optional(
+                    report
+                        .flake_statistics
+                        .map(|statistics| statistics.mean_length.to_string()),
+                ),
+                optional(
+                    report
+                        .flake_statistics
+                        .map(|statistics| statistics.order_parameter.to_string()),
+                ),
+            ]),
+            Err(e) => record.extend([
+                "error".to_string(),
+                e.clone(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ]),
+        }
+
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()
+}
+
+/// Draws the mean exclusion ratio (over the images and the other parameters) for every
+/// combination of the values of two parameters
+pub fn plot_heatmap(
+    path: &Path,
+    x: &Parameter,
+    y: &Parameter,
+    (i, j): (usize, usize),
+    rows: &[SweepRow],
+) {
+    // Average the exclusion ratios in each cell
+    let mut cells: BTreeMap<(usize, usize), (f64, usize)> = BTreeMap::new();
+    for row in rows {
+        if let Ok(report) = &row.result {
+            if let Some(ratio) = report.radius_adjusted_ratio.or(report.exclusion_ratio) {
+                let cell = cells.entry((row.indices[i], row.indices[j])).or_default();
+                cell.0 += f64::from(ratio);
+                cell.1 += 1;
+            }
+        }
+    }
+
+    let means: BTreeMap<(usize, usize), f64> = cells
+        .into_iter()
+        .map(|(cell, (sum, count))| (cell, sum / count as f64))
+        .collect();
+    let minimum = means.values().copied().fold(f64::INFINITY, f64::min);
+    let maximum = means.values().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    let canvas = BitMapBackend::new(path, (PLOT_WIDTH, PLOT_HEIGHT)).into_drawing_area();
+    canvas.fill(&WHITE).unwrap();
+
+    // Create a chart with a caption
+    let mut chart = ChartBuilder::on(&canvas)
+        .x_label_area_size(35)
+        .y_label_area_size(50)
+        .caption(
+            format!(
+                "Exclusion ratio ({:.2}% - {:.2}%)",
+                100.0 * minimum,
+                100.0 * maximum
+            ),
+            ("sans-serif", 30),
+        )
+        .margin(15)
+        // The segments include the end of the range
+        .build_cartesian_2d(
+            (0..x.values.len() - 1).into_segmented(),
+            (0..y.values.len() - 1).into_segmented(),
+        )
+        .unwrap();
+
+    // Label the cells by the values of the parameters
+    let label = |parameter: &Parameter, segment: &SegmentValue<usize>| match segment {
+        SegmentValue::Exact(index) | SegmentValue::CenterOf(index) => parameter
+            .values
+            .get(*index)
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        SegmentValue::Last => String::new(),
+    };
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .x_label_formatter(&|segment| label(x, segment))
+        .y_label_formatter(&|segment| label(y, segment))
+        .x_desc(x.name())
+        .y_desc(y.name())
+        .axis_desc_style(("sans-serif", 15))
+        .draw()
+        .unwrap();
+
+    chart
+        .draw_series(means.iter().map(|(&(a, b), mean)| {
+            Rectangle::new(
+                [
+                    (SegmentValue::Exact(a), SegmentValue::Exact(b)),
+                    (SegmentValue::Exact(a + 1), SegmentValue::Exact(b + 1)),
+                ],
+                ViridisRGB::get_color_normalized(*mean, minimum, maximum.max(minimum + 1e-9))
+                    .filled(),
+            )
+        }))
+        .unwrap();
+
+    // Write the value in each cell if they are large enough
+    if x.values.len() <= LABELLED_CELLS && y.values.len() <= LABELLED_CELLS {
+        chart
+            .draw_series(means.iter().map(|(&(a, b), mean)| {
+                Text::new(
+                    format!("{:.1}%", 100.0 * mean),
+                    (SegmentValue::CenterOf(a), SegmentValue::CenterOf(b)),
+                    // Dark text on the bright end of the color map and light on the dark
+                    ("sans-serif", 12).into_font().color(
+                        if (mean - minimum) / (maximum - minimum).max(1e-9) > 0.6 {
+                            &BLACK
+                        } else {
+                            &WHITE
+                        },
+                    ),
+                )
+            }))
+            .unwrap();
+    }
+
+    canvas.present().expect("Failed to draw plot");
+}
@@ -0,0 +1,28 @@
+/// Parses a length with a unit such as "3.721 nm" or "2 µm" and returns it in micrometers. The
+/// unit may be separated from the number by whitespace
+pub fn parse_length(text: &str) -> Option<f32> {
+    let text = text.trim();
+
+    // Split the number from the unit at the first character that can't be part of a number
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f32 = number.trim().replace(',', ".").parse().ok()?;
+
+    Some(value * micrometers_per_unit(unit.trim())?)
+}
+
+/// The number of micrometers in one of the given unit
+pub fn micrometers_per_unit(unit: &str) -> Option<f32> {
+    match unit {
+        "pm" => Some(1e-6),
+        "nm" => Some(1e-3),
+        // Both the micro sign and the greek letter mu are used in the wild
+        "um" | "µm" | "μm" => Some(1.0),
+        "mm" => Some(1e3),
+        "m" => Some(1e6),
+        _ => None,
+    }
+}
@@ -0,0 +1,197 @@
+use graphene_analysis::{
+    algorithms::{Detection, Exclusion, Flake, FlakeStatistics, Scale},
+    artifacts::{Artifact, MemorySink},
+    Configuration, Input, Timings,
+};
+use serde::Serialize;
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use super::{body, multipart, pipeline, ContentType, HttpResponse, HttpStatus, RUNS};
+
+/// The outcome of an analysis, as returned by `POST /api/analyse`
+#[derive(Serialize)]
+struct Analysis {
+    run_id: String,
+    scale: Scale,
+    bacteria_exclusion: Option<Exclusion>,
+    flake_statistics: Option<FlakeStatistics>,
+    flakes: Option<Vec<Flake>>,
+    bacteria: Option<Detection>,
+    warnings: Vec<String>,
+    timings: StageTimings,
+    artifacts: Vec<ArtifactEntry>,
+}
+
+/// The time spent in each stage in seconds
+#[derive(Serialize)]
+struct StageTimings {
+    scale: f64,
+    pre_processing: f64,
+    bacteria_exclusion: f64,
+    graphene_angles: f64,
+    bacteria_detection: f64,
+    total: f64,
+}
+
+impl From<Timings> for StageTimings {
+    fn from(timings: Timings) -> Self {
+        StageTimings {
+            scale: timings.scale.as_secs_f64(),
+            pre_processing: timings.pre_processing.as_secs_f64(),
+            bacteria_exclusion: timings.bacteria_exclusion.as_secs_f64(),
+            graphene_angles: timings.graphene_angles.as_secs_f64(),
+            bacteria_detection: timings.bacteria_detection.as_secs_f64(),
+            total: timings.total().as_secs_f64(),
+        }
+    }
+}
+
+/// An artifact of a run and where to download it
+#[derive(Serialize)]
+struct ArtifactEntry {
+    name: String,
+    content_type: &'static str,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Serves the API endpoints under `/api/`
+pub fn route(
+    method: &tiny_http::Method,
+    path: &str,
+    content_type: Option<&str>,
+    data: &[u8],
+) -> HttpResponse {
+    use tiny_http::Method;
+
+    match (method, path) {
+        (Method::Post, "/api/analyse") => analyse(content_type, data),
+        (Method::Get, "/api/config/default") => json(HttpStatus::Ok, &Configuration::default()),
+        (Method::Get, path) => {
+            let run = path
+                .strip_prefix("/api/runs/")
+                .and_then(|path| path.strip_suffix("/artifacts"));
+
+            match run {
+                Some(id) => match RUNS.get(id) {
+                    Some(artifacts) => json(HttpStatus::Ok, &artifact_entries(id, &artifacts)),
+                    None => error(HttpStatus::NotFound, format!("There is no run {id:?}")),
+                },
+                None => error(HttpStatus::NotFound, "Unknown endpoint"),
+            }
+        }
+        _ => error(
+            HttpStatus::MethodNotAllowed,
+            "Unknown endpoint for the method",
+        ),
+    }
+}
+
+/// Analyses an image uploaded as the `image` field of a `multipart/form-data` body, with an
+/// optional JSON configuration in the `config` field (the default configuration otherwise)
+fn analyse(content_type: Option<&str>, data: &[u8]) -> HttpResponse {
+    let Some(boundary) = content_type.and_then(multipart::boundary) else {
+        return error(
+            HttpStatus::UnsupportedMediaType,
+            "Expected a multipart/form-data body",
+        );
+    };
+
+    let Some(parts) = multipart::parse(data, boundary) else {
+        return error(HttpStatus::BadRequest, "Malformed multipart body");
+    };
+
+    let Some(image) = parts.iter().find(|part| part.name == "image") else {
+        return error(HttpStatus::BadRequest, "Missing the image field");
+    };
+
+    let config = match parts.iter().find(|part| part.name == "config") {
+        Some(part) => match serde_json::from_slice::<Configuration>(part.content) {
+            Ok(config) => config,
+            Err(e) => {
+                return error(
+                    HttpStatus::BadRequest,
+                    format!("Invalid configuration ({e})"),
+                )
+            }
+        },
+        None => Configuration::default(),
+    };
+
+    let input = match Input::from_bytes(image.content) {
+        Ok(input) => input,
+        Err(e) => {
+            return error(
+                HttpStatus::BadRequest,
+                format!("Could not load image ({e})"),
+            )
+        }
+    };
+
+    // Run all the enabled stages on the image, keeping the artifacts for later download
+    let artifacts = MemorySink::new();
+    let result = match pipeline(&config).run(input, &artifacts) {
+        Ok(result) => result,
+        Err(e) => return error(HttpStatus::UnprocessableEntity, e),
+    };
+
+    let run_id = RUNS.insert(artifacts.into_artifacts());
+    let artifacts = RUNS
+        .get(&run_id)
+        .map(|artifacts| artifact_entries(&run_id, &artifacts))
+        .unwrap_or_default();
+
+    json(
+        HttpStatus::Ok,
+        &Analysis {
+            run_id,
+            scale: result.scale,
+            bacteria_exclusion: result.bacteria_exclusion,
+            flake_statistics: result.flakes.as_deref().and_then(FlakeStatistics::new),
+            flakes: result.flakes,
+            bacteria: result.bacteria,
+            warnings: result.warnings,
+            timings: result.timings.into(),
+            artifacts,
+        },
+    )
+}
+
+/// Lists the artifacts of a run with their download urls
+fn artifact_entries(run_id: &str, artifacts: &BTreeMap<String, Artifact>) -> Vec<ArtifactEntry> {
+    artifacts
+        .iter()
+        .map(|(name, artifact)| ArtifactEntry {
+            name: name.clone(),
+            content_type: artifact.content_type(),
+            url: format!("/runs/{run_id}/{name}"),
+        })
+        .collect()
+}
+
+/// Creates a JSON response
+fn json(status: HttpStatus, value: &impl Serialize) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(data) => body(status, ContentType::Json, data),
+        Err(e) => body(
+            HttpStatus::InternalServerError,
+            ContentType::Text,
+            format!("Failed to serialize response ({e})"),
+        ),
+    }
+}
+
+/// Creates a JSON error response, with the message under `error`
+fn error(status: HttpStatus, message: impl Display) -> HttpResponse {
+    json(
+        status,
+        &ErrorBody {
+            error: message.to_string(),
+        },
+    )
+}
@@ -1,189 +1,445 @@
 use git_version::git_version;
+use graphene_analysis::{
+    artifacts::{Artifact, MemorySink},
+    cache::DiskCache,
+    configuration::{EdgeDetector, ExclusionMode, OcrEngine, Polarity, ThresholdSelection},
+    Configuration, Input, Pipeline,
+};
 use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError};
-use image::open;
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use self::store::Store;
+
+mod api;
+mod multipart;
+mod store;
 
 use std::{
-    fmt::{self, Display},
+    collections::BTreeMap,
+    fmt::{self, Display, Write as _},
     fs,
-    io::{BufRead, BufReader, Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
+    io::{Cursor, Read},
+    net::SocketAddr,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
     path::PathBuf,
-};
-
-use crate::{
-    algorithms::{bacteria_exclusion, determine_scale, graphene_angles, pre_processing},
-    configuration::Configuration,
+    str::FromStr,
+    string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, OnceLock,
+    },
+    thread,
+    time::Duration,
 };
 
 /// The HTML template used for the web interface
 static INTERACTIVE_TEMPLATE: &str = include_str!("./template.html");
 
 /// The handlebar instance used to generate the page
-static HANDLEBARS: Lazy<Handlebars> = Lazy::new(create_handlebars);
-
-// Paths to all static images generated by the program
-static IMAGES: &[&str] = &[
-    "/graphene.png",
-    "/bacteria.png",
-    "/angle-histogram.png",
-    "/length-histogram.png",
-    "/angles.png",
-    "/artifacts.png",
-    "/bacteria-exclusion.png",
-];
-
-// Starts listening for requests on the given socket address
-pub fn start(address: SocketAddr) {
+static HANDLEBARS: LazyLock<Handlebars> = LazyLock::new(create_handlebars);
+
+/// The artifacts of the most recent analyses, by run id
+static RUNS: LazyLock<Store<BTreeMap<String, Artifact>>> =
+    LazyLock::new(|| Store::new(RUN_CAPACITY));
+
+/// The most recently uploaded images, by upload id
+static UPLOADS: LazyLock<Store<Upload>> = LazyLock::new(|| Store::new(UPLOAD_CAPACITY));
+
+/// Where the outputs of the stages are reused from, if caching is enabled
+static CACHE: OnceLock<DiskCache> = OnceLock::new();
+
+/// The largest request body accepted in bytes, which also limits the size of uploaded images
+static MAX_BODY: OnceLock<u64> = OnceLock::new();
+
+/// The number of analyses kept in memory for viewing their artifacts
+const RUN_CAPACITY: usize = 8;
+
+/// The number of uploaded images kept in memory for further tuning of the parameters
+const UPLOAD_CAPACITY: usize = 8;
+
+/// How often the workers check if the server should shut down while waiting for requests
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A complete HTTP response with its content in memory
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+/// An image uploaded through the interface
+struct Upload {
+    /// The original file name
+    name: String,
+    /// The encoded image, kept as is to keep the metadata
+    data: Vec<u8>,
+}
+
+// Starts listening for requests on the given socket address, until interrupted by Ctrl-C.
+// Requests with a body larger than `max_body` bytes are refused
+pub fn start(address: SocketAddr, max_body: u64, cache: Option<DiskCache>) {
+    MAX_BODY
+        .set(max_body)
+        .expect("The webserver was already started");
+    if let Some(cache) = cache {
+        CACHE.set(cache).expect("The webserver was already started");
+    }
+
     println!("Starting webserver on http://{address}");
-    let listener = TcpListener::bind(address).expect("Failed to bind to address");
+    let server = Server::http(address).expect("Failed to bind to address");
+
+    // Stop accepting new requests on Ctrl-C, letting the requests being handled finish
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = Arc::clone(&running);
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))
+            .expect("Failed to set the Ctrl-C handler");
+    }
 
-    for stream in listener.incoming() {
-        handle_connection(stream.unwrap());
+    // Handle requests on one worker per core, so a long analysis doesn't block the interface
+    let workers = thread::available_parallelism().map_or(4, NonZeroUsize::get);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while running.load(Ordering::SeqCst) {
+                    match server.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                        Ok(Some(request)) => handle_request(request),
+                        Ok(None) => (),
+                        Err(e) => {
+                            eprintln!("Failed to receive request ({e})");
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    println!("Stopped webserver");
+}
+
+/// The largest request body accepted in bytes
+fn max_body() -> u64 {
+    MAX_BODY.get().copied().unwrap_or(u64::MAX)
+}
+
+/// A pipeline reusing the outputs of the stages from the cache, if enabled. Tuning the later
+/// stages of an uploaded image skips the earlier ones this way
+fn pipeline(config: &Configuration) -> Pipeline<'_> {
+    match CACHE.get() {
+        Some(cache) => Pipeline::new(config).with_cache(cache),
+        None => Pipeline::new(config),
     }
 }
 
 /// A HTTP status
+#[derive(Clone, Copy)]
 enum HttpStatus {
     Ok,
+    BadRequest,
     NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    UnprocessableEntity,
     InternalServerError,
 }
 
+impl HttpStatus {
+    fn code(self) -> u16 {
+        match self {
+            HttpStatus::Ok => 200,
+            HttpStatus::BadRequest => 400,
+            HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::UnsupportedMediaType => 415,
+            HttpStatus::UnprocessableEntity => 422,
+            HttpStatus::InternalServerError => 500,
+        }
+    }
+}
+
 impl Display for HttpStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            HttpStatus::Ok => write!(f, "HTTP/1.1 200 OK"),
-            HttpStatus::NotFound => write!(f, "HTTP/1.1 404 Not Found"),
-            HttpStatus::InternalServerError => write!(f, "HTTP/1.1 500 Internal Server Error"),
+            HttpStatus::Ok => write!(f, "OK"),
+            HttpStatus::BadRequest => write!(f, "Bad Request"),
+            HttpStatus::NotFound => write!(f, "Not Found"),
+            HttpStatus::MethodNotAllowed => write!(f, "Method Not Allowed"),
+            HttpStatus::PayloadTooLarge => write!(f, "Payload Too Large"),
+            HttpStatus::UnsupportedMediaType => write!(f, "Unsupported Media Type"),
+            HttpStatus::UnprocessableEntity => write!(f, "Unprocessable Entity"),
+            HttpStatus::InternalServerError => write!(f, "Internal Server Error"),
         }
     }
 }
 
-/// Creates a complete HTTP request from status and content
-fn body_byte(status: HttpStatus, content: &[u8]) -> Vec<u8> {
-    let status_line = status.to_string();
-    let length = content.len();
-
-    // Create the response
-    let mut bytes = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n")
-        .as_bytes()
-        .to_vec();
-    bytes.extend(content.iter());
+/// The content types served
+#[derive(Clone, Copy)]
+enum ContentType {
+    Html,
+    Png,
+    Csv,
+    Json,
+    Text,
+}
 
-    bytes
+impl Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentType::Html => write!(f, "text/html; charset=utf-8"),
+            ContentType::Png => write!(f, "image/png"),
+            ContentType::Csv => write!(f, "text/csv; charset=utf-8"),
+            ContentType::Json => write!(f, "application/json"),
+            ContentType::Text => write!(f, "text/plain; charset=utf-8"),
+        }
+    }
 }
 
-/// Creates a complete HTTP request from status and content
-fn body(status: HttpStatus, content: &str) -> String {
-    let status_line = status.to_string();
-    let length = content.len();
+/// Creates a complete HTTP response from status, content type and content
+fn body(
+    status: HttpStatus,
+    content_type: ContentType,
+    content: impl Into<Vec<u8>>,
+) -> HttpResponse {
+    let header = Header::from_bytes("Content-Type", content_type.to_string())
+        .expect("Invalid content type header");
+
+    Response::from_data(content.into())
+        .with_status_code(status.code())
+        .with_header(header)
+}
 
-    format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{content}")
+/// Creates a plain text response describing the status
+fn status_body(status: HttpStatus) -> HttpResponse {
+    body(
+        status,
+        ContentType::Text,
+        format!("{} {status}", status.code()),
+    )
 }
 
-/// Handles incomming HTTP requests
-fn handle_connection(mut stream: TcpStream) {
-    // Extract http headers from request
-    let buf_reader = BufReader::new(&mut stream);
-    let http_header: Vec<_> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-
-    // Get the requested path
-    let full_path = match http_header.first() {
-        Some(status) if status.starts_with("GET") => {
-            let full_path = status.split_whitespace().nth(1).unwrap();
-            full_path
+/// Handles incoming HTTP requests, refusing bodies larger than the maximum
+fn handle_request(mut request: Request) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.to_string());
+
+    // Extract path and query
+    let (path, query) = if let Some((path, query)) = url.split_once('?') {
+        (path, Some(query))
+    } else {
+        (url.as_str(), None)
+    };
+
+    println!("Serving: {method} \"{path}\"");
+
+    // Read the body of posted forms and uploads
+    let mut data = Vec::new();
+    if method == Method::Post {
+        let max_body = max_body();
+
+        // Refuse bodies that are too large before reading them
+        if request
+            .body_length()
+            .is_some_and(|length| max_body < length as u64)
+        {
+            respond(request, status_body(HttpStatus::PayloadTooLarge));
+            return;
         }
-        _ => {
-            stream
-                .write_all(body(HttpStatus::NotFound, "").as_bytes())
-                .unwrap();
+
+        // Chunked bodies have no length up front, so read at most one byte past the limit
+        if let Err(e) = request
+            .as_reader()
+            .take(max_body.saturating_add(1))
+            .read_to_end(&mut data)
+        {
+            eprintln!("Failed to read request body ({e})");
+            respond(request, status_body(HttpStatus::BadRequest));
             return;
         }
+        if max_body < data.len() as u64 {
+            respond(request, status_body(HttpStatus::PayloadTooLarge));
+            return;
+        }
+    }
+
+    // Respond with an error instead of bringing down the worker if anything panics
+    let response = panic::catch_unwind(AssertUnwindSafe(|| {
+        route(&method, path, query, content_type.as_deref(), &data)
+    }))
+    .unwrap_or_else(|_| status_body(HttpStatus::InternalServerError));
+
+    respond(request, response);
+}
+
+/// Sends the response, a failure means the client went away which doesn't need any handling
+fn respond(request: Request, response: HttpResponse) {
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send response ({e})");
+    }
+}
+
+/// Serves the relevant content for the path
+fn route(
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    content_type: Option<&str>,
+    data: &[u8],
+) -> HttpResponse {
+    match (method, path) {
+        // Serve interface, where posted forms have the parameters in the body
+        (Method::Get, "/") => match query.map(parse_query).transpose() {
+            Ok(parameters) => serve_interface(&parameters.unwrap_or_default(), None),
+            Err(e) => invalid_parameters(e),
+        },
+        (Method::Post, "/") => serve_form(content_type, data),
+
+        // Serve the JSON API
+        (method, path) if path.starts_with("/api/") => api::route(method, path, content_type, data),
+
+        // Serve the images and tables of an analysis
+        (Method::Get, path) if path.starts_with("/runs/") => serve_artifact(path),
+
+        // Only the interface can be posted to
+        (_, "/") => status_body(HttpStatus::MethodNotAllowed),
+        (_, path) if path.starts_with("/runs/") => status_body(HttpStatus::MethodNotAllowed),
+
+        // Return the 404 Not Found for invalid routes
+        _ => status_body(HttpStatus::NotFound),
+    }
+}
+
+/// Serves an artifact of an analysis from a path of the form `/runs/<id>/<artifact>`
+fn serve_artifact(path: &str) -> HttpResponse {
+    let Some((id, name)) = path.trim_start_matches("/runs/").split_once('/') else {
+        return status_body(HttpStatus::NotFound);
     };
 
-    // Extract path and query
-    let (path, query) = if let Some((path, query)) = full_path.split_once("?") {
-        (path, Some(query))
-    } else {
-        (full_path, None)
+    // Old runs are evicted, which is reported as not found like unknown runs
+    let Some(artifact) = RUNS
+        .get(id)
+        .and_then(|artifacts| artifacts.get(name).cloned())
+    else {
+        return status_body(HttpStatus::NotFound);
     };
 
-    println!("Serving: \"{path}\"");
+    let content_type = match artifact {
+        Artifact::Image(_) => ContentType::Png,
+        Artifact::Table(_) => ContentType::Csv,
+    };
 
-    // Serve the relevant content
-    match path {
-        // Serve interface
-        "/" => serve_interface(query, &mut stream),
+    match artifact.encode() {
+        Ok(data) => body(HttpStatus::Ok, content_type, data),
+        Err(e) => body(
+            HttpStatus::InternalServerError,
+            ContentType::Text,
+            format!("Failed to encode {name} ({e})"),
+        ),
+    }
+}
 
-        // Serve the static images
-        path if IMAGES.contains(&path) => {
-            let prefix = match fs::read_to_string("./output/tmp") {
-                Ok(prefix) => prefix,
-                Err(_) => {
-                    stream
-                        .write_all(body(HttpStatus::NotFound, "").as_bytes())
-                        .unwrap();
-                    return;
-                }
-            };
+/// Renders the interface with the given settings
+fn render(status: HttpStatus, settings: &InteractiveConfiguration) -> HttpResponse {
+    match HANDLEBARS.render("index", settings) {
+        Ok(html) => body(status, ContentType::Html, html),
+        Err(e) => body(
+            HttpStatus::InternalServerError,
+            ContentType::Text,
+            format!("Failed to render the interface ({e})"),
+        ),
+    }
+}
 
-            if let Ok(mut res) = fs::File::open(prefix + path.trim_start_matches('/')) {
-                let mut data = Vec::new();
-                res.read_to_end(&mut data).unwrap();
+/// Renders the default interface with an error about the parameters
+fn invalid_parameters(error: impl Display) -> HttpResponse {
+    let settings = InteractiveConfiguration {
+        error: format!("Invalid parameters ({error})"),
+        ..Default::default()
+    };
 
-                stream.write_all(&body_byte(HttpStatus::Ok, &data)).unwrap();
-            } else {
-                stream
-                    .write_all(body(HttpStatus::NotFound, "").as_bytes())
-                    .unwrap();
+    render(HttpStatus::BadRequest, &settings)
+}
+
+/// Splits a url encoded query or form into its decoded parameters, where spaces may be
+/// encoded as '+'
+fn parse_query(query: &str) -> Result<Vec<(String, String)>, FromUtf8Error> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = urlencoding::decode(&value.replace('+', " "))?.into_owned();
+
+            Ok((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Serves the interface for a posted form, which is multipart if it contains an uploaded image
+fn serve_form(content_type: Option<&str>, data: &[u8]) -> HttpResponse {
+    let Some(boundary) = content_type.and_then(multipart::boundary) else {
+        return match std::str::from_utf8(data).map(parse_query) {
+            Ok(Ok(parameters)) => serve_interface(&parameters, None),
+            Ok(Err(e)) => invalid_parameters(e),
+            Err(e) => invalid_parameters(e),
+        };
+    };
+
+    let Some(parts) = multipart::parse(data, boundary) else {
+        return invalid_parameters("malformed multipart form");
+    };
+
+    // The file input is empty if no file was chosen, then the previous upload is kept
+    let mut parameters = Vec::new();
+    let mut upload = None;
+    for part in parts {
+        if part.name == "image" {
+            if !part.content.is_empty() {
+                upload = Some(Upload {
+                    name: part.filename.unwrap_or_default(),
+                    data: part.content.to_vec(),
+                });
             }
+        } else {
+            parameters.push((
+                part.name,
+                String::from_utf8_lossy(part.content).into_owned(),
+            ));
         }
+    }
 
-        // Return the 404 Not Found for invalid routes
-        _ => {
-            stream
-                .write_all(body(HttpStatus::NotFound, "").as_bytes())
-                .unwrap();
-        }
-    };
+    serve_interface(&parameters, upload)
 }
 
-/// Serves the default interface
-fn serve_interface(query: Option<&str>, stream: &mut TcpStream) {
-    // Derive the configuration from the query
-    let mut settings = if let Some(query) = query {
-        InteractiveConfiguration::from_query(&urlencoding::decode(query).unwrap())
-            .expect("Failed to parse parameters")
-    } else {
-        InteractiveConfiguration::default()
+/// Serves the default interface, analysing the uploaded image if there is one
+fn serve_interface(parameters: &[(String, String)], upload: Option<Upload>) -> HttpResponse {
+    // Derive the configuration from the parameters
+    let mut settings = match InteractiveConfiguration::from_parameters(parameters) {
+        Ok(settings) => settings,
+        Err(e) => return invalid_parameters(e),
     };
 
+    // Keep new uploads for further tuning, they replace any previous upload
+    if let Some(upload) = upload {
+        settings.upload = UPLOADS.insert(upload);
+    }
+
     // Save configuration if needed
     if settings.save_config {
-        let config_string =
-            toml::to_string_pretty(&settings.config).expect("Failed to serialize configuration");
-
-        match fs::write(&settings.configuration_path, config_string) {
-            Ok(_) => (),
-            Err(e) => {
-                settings.error = format!("Could not write to config file ({e})");
-                stream
-                    .write_all(
-                        body(
-                            HttpStatus::InternalServerError,
-                            &HANDLEBARS.render("index", &settings).unwrap(),
-                        )
-                        .as_bytes(),
-                    )
-                    .unwrap();
-                return;
-            }
+        let result = toml::to_string_pretty(&settings.config)
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                fs::write(&settings.configuration_path, config).map_err(|e| e.to_string())
+            });
+
+        if let Err(e) = result {
+            settings.error = format!("Could not write to config file ({e})");
+            return render(HttpStatus::InternalServerError, &settings);
         }
     }
 
@@ -193,21 +449,17 @@ fn serve_interface(query: Option<&str>, stream: &mut TcpStream) {
             Ok(config) => config,
             Err(e) => {
                 settings.error = format!("Failed to read the config file ({e})");
-                stream
-                    .write_all(
-                        body(
-                            HttpStatus::InternalServerError,
-                            &HANDLEBARS.render("index", &settings).unwrap(),
-                        )
-                        .as_bytes(),
-                    )
-                    .unwrap();
-                return;
+                return render(HttpStatus::InternalServerError, &settings);
             }
         };
 
-        settings.config =
-            toml::from_str(&config_string).expect("Couldn't parse the config file as TOML");
+        settings.config = match toml::from_str(&config_string) {
+            Ok(config) => config,
+            Err(e) => {
+                settings.error = format!("Couldn't parse the config file as TOML ({e})");
+                return render(HttpStatus::BadRequest, &settings);
+            }
+        };
 
         // Warn about config using another version of the program
         if settings.config.program_version != git_version!() {
@@ -215,127 +467,79 @@ fn serve_interface(query: Option<&str>, stream: &mut TcpStream) {
         }
     }
 
-    // Load the given image
-    let image = match open(&settings.path) {
-        Ok(image) => image,
+    // Load the uploaded image, or the image at the given path if nothing is uploaded
+    let input = if settings.upload.is_empty() {
+        Input::open(&settings.path)
+    } else {
+        let Some(upload) = UPLOADS.get(&settings.upload) else {
+            settings.upload.clear();
+            settings.error =
+                "The uploaded image is no longer available, please upload it again".to_string();
+            return render(HttpStatus::NotFound, &settings);
+        };
+
+        settings.upload_name.clone_from(&upload.name);
+        Input::from_bytes(&upload.data)
+    };
+
+    let input = match input {
+        Ok(input) => input,
         Err(e) => {
             settings.error = format!("Could not load input image ({e})");
-            stream
-                .write_all(
-                    body(
-                        HttpStatus::InternalServerError,
-                        &HANDLEBARS.render("index", &settings).unwrap(),
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-            return;
+            return render(HttpStatus::BadRequest, &settings);
         }
-    }
-    .to_luma8();
-
-    // Create an output prefix from the filename
-    let output_prefix =
-        "./output/".to_string() + settings.path.file_stem().unwrap().to_str().unwrap() + "_";
-
-    // Determine the scale (um / px)
-    let (scale, um, px, scale_bar_height, image) = match determine_scale(
-        image,
-        &settings.config.text_recognition,
-        true,
-        &output_prefix,
-    ) {
+    };
+
+    // Keep the artifacts in memory to serve them under the id of this run
+    let artifacts = MemorySink::new();
+
+    // Run all the enabled stages on the image
+    let result = match pipeline(&settings.config).run(input, &artifacts) {
         Ok(result) => result,
         Err(e) => {
-            settings.error = format!("Failed to determine scale of image ({e})");
-            stream
-                .write_all(
-                    body(
-                        HttpStatus::InternalServerError,
-                        &HANDLEBARS.render("index", &settings).unwrap(),
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-            return;
+            settings.error = e.to_string();
+            return render(HttpStatus::InternalServerError, &settings);
         }
     };
 
+    let scale = result.scale;
     settings.scale = format!(
-        "Scale: {:.4} um/px (px: {}, um: {}, scale bar height: {})",
-        scale, px, um, scale_bar_height
+        "Scale: {:.4} um/px (px: {}, um: {}, scale bar height: {}, source: {}, confidence: {:.2})",
+        scale.scale,
+        scale.pixels,
+        scale.micrometers,
+        scale.scale_bar_height,
+        scale.source,
+        scale.confidence
     );
+    for warning in &result.warnings {
+        write!(settings.scale, ". Warning: {warning}").unwrap();
+    }
     println!("{}", settings.scale);
 
-    // Preprocessing
-    let image = pre_processing(image, settings.config.pre_processing);
-
-    // Find graphene and determine bacteria exclusion percentage
-    if settings.config.bacteria_exclusion.enabled {
-        let bacteria_exclusion = match bacteria_exclusion(
-            &image,
-            &settings.config.bacteria_exclusion,
-            scale,
-            true,
-            &output_prefix,
-        ) {
-            Ok(bacteria_exclusion) => bacteria_exclusion,
-            Err(e) => {
-                settings.error = format!("Failed to calculate bacteria exclusion ({e})");
-                stream
-                    .write_all(
-                        body(
-                            HttpStatus::InternalServerError,
-                            &HANDLEBARS.render("index", &settings).unwrap(),
-                        )
-                        .as_bytes(),
-                    )
-                    .unwrap();
-                return;
-            }
-        };
-
+    if let Some(bacteria_exclusion) = result.bacteria_exclusion {
         settings.bacteria_exclusion = format!(
             "Estimated bacteria exclusion: {:.3}%",
-            bacteria_exclusion * 100.0
+            bacteria_exclusion.value() * 100.0
         );
         println!("{}", settings.bacteria_exclusion);
     }
 
-    // Find angles of graphene in the image
-    if settings.config.graphene_angles.enabled {
-        graphene_angles(
-            &image,
-            &settings.config.graphene_angles,
-            scale,
-            true,
-            &output_prefix,
+    if let Some(detection) = &result.bacteria {
+        settings.bacteria_coverage = format!(
+            "Bacteria found: {} ({:.0} per mm², {:.3}% coverage, {:.3}% rejected)",
+            detection.count(),
+            detection.density,
+            detection.coverage * 100.0,
+            detection.rejected_coverage * 100.0
         );
+        println!("{}", settings.bacteria_coverage);
     }
 
-    // Export the used configuration to the output directory
-    fs::write(
-        output_prefix.to_string() + "config.toml",
-        toml::to_string_pretty(&settings.config).expect("Failed to serialize configuration"),
-    )
-    .expect("Failed to save output configuration file");
-
-    // Export that prefix to a temporary file as a workaround to be able to know the file
-    // prefix from the image serving below
-    let mut tmp = fs::File::create("./output/tmp").expect("Failed to create temporary file");
-    write!(tmp, "{output_prefix}").expect("Failed to write to temporary file");
+    settings.run_id = RUNS.insert(artifacts.into_artifacts());
 
     // Render the configuration using HANDLEBARS
-    stream
-        .write_all(
-            body(
-                HttpStatus::Ok,
-                &HANDLEBARS.render("index", &settings).unwrap(),
-            )
-            .as_bytes(),
-        )
-        .unwrap();
-    return;
+    render(HttpStatus::Ok, &settings)
 }
 
 fn create_handlebars() -> Handlebars<'static> {
@@ -421,7 +625,7 @@ fn create_handlebars() -> Handlebars<'static> {
         Box::new(
             |h: &Helper,
              _: &Handlebars,
-             _: &Context,
+             context: &Context,
              _: &mut RenderContext,
              out: &mut dyn Output|
              -> HelperResult {
@@ -435,12 +639,19 @@ fn create_handlebars() -> Handlebars<'static> {
                     .ok_or_else(|| RenderError::new("The flag is not a boolean"))?
                     && h.param(3).and_then(|v| v.value().as_bool()).unwrap_or(true);
 
+                // The images are served from the current run, which there is none of on errors
+                let run_id = context
+                    .data()
+                    .get("run_id")
+                    .and_then(|id| id.as_str())
+                    .unwrap_or_default();
+
                 let image_source = h.param(1).ok_or_else(|| {
                     RenderError::new("The second parameter was not found, of at least 2")
                 })?;
 
                 // Only show the image if flag is on
-                if flag {
+                if flag && !run_id.is_empty() {
                     if let Some(title) = h.param(2) {
                         out.write(&format!(
                             "<h3>{}</h3>",
@@ -453,7 +664,7 @@ fn create_handlebars() -> Handlebars<'static> {
                     }
 
                     out.write(&format!(
-                        "<img src=\"{}\">",
+                        "<img src=\"/runs/{run_id}/{}\">",
                         image_source
                             .value()
                             .as_str()
@@ -489,6 +700,14 @@ struct InteractiveConfiguration {
     save_config: bool,
     load_config: bool,
     error: String,
+    /// The id of the uploaded image to analyse instead of the path, empty if there is none
+    upload: String,
+    /// The file name of the uploaded image
+    upload_name: String,
+    /// The largest image that can be uploaded in bytes
+    max_upload: u64,
+    /// The id of the run with the artifacts to show, empty if nothing was analysed
+    run_id: String,
     scale: String,
     artifacts: String,
     bacteria_exclusion: String,
@@ -504,6 +723,10 @@ impl Default for InteractiveConfiguration {
             save_config: false,
             load_config: false,
             error: String::new(),
+            upload: String::new(),
+            upload_name: String::new(),
+            max_upload: max_body(),
+            run_id: String::new(),
             scale: String::new(),
             artifacts: String::new(),
             bacteria_exclusion: String::new(),
@@ -513,24 +736,38 @@ impl Default for InteractiveConfiguration {
 }
 
 impl InteractiveConfiguration {
-    fn from_query(query: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    fn from_parameters(
+        parameters: &[(String, String)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         // Use the default configuration as a base
         let mut settings = Self::default();
 
-        // Get all query parameters from query string
-        for (name, value) in query.split('&').map(|pair| {
-            let mut pair = pair.split('=');
-            (pair.next().unwrap(), pair.next().unwrap())
-        }) {
+        for (name, value) in parameters {
+            let (name, value) = (name.as_str(), value.as_str());
+
             match name {
                 // General
-                "path" => {
-                    settings.path =
-                        PathBuf::from(PathBuf::from(value).to_str().unwrap().replace('+', " "));
-                }
+                "path" => settings.path = PathBuf::from(value),
+                "upload" => settings.upload = value.to_string(),
                 // Text recognition
+                "use_metadata" => {
+                    if value == "no" {
+                        settings.config.text_recognition.use_metadata = false;
+                    } else if value == "on" {
+                        settings.config.text_recognition.use_metadata = true;
+                    }
+                }
+                "text_engine" => {
+                    settings.config.text_recognition.engine = match value {
+                        "builtin" => OcrEngine::Builtin,
+                        _ => OcrEngine::Tesseract,
+                    };
+                }
+                "footer_profile" => {
+                    settings.config.text_recognition.profile = value.to_string();
+                }
                 "text_bar_height" => {
-                    settings.config.text_recognition.scale_bar_height = value.parse().unwrap();
+                    settings.config.text_recognition.scale_bar_height = parse(name, value)?;
                 }
                 "override_scale" => {
                     if value == "no" {
@@ -540,11 +777,11 @@ impl InteractiveConfiguration {
                     }
                 }
                 "override_scale_pixels" => {
-                    settings.config.text_recognition.override_scale_pixels = value.parse().unwrap();
+                    settings.config.text_recognition.override_scale_pixels = parse(name, value)?;
                 }
                 "override_scale_micrometers" => {
                     settings.config.text_recognition.override_scale_micrometers =
-                        value.parse().unwrap();
+                        parse(name, value)?;
                 }
                 // Bacteria exclusion
                 "bacteria_exclusion" => {
@@ -555,13 +792,31 @@ impl InteractiveConfiguration {
                     }
                 }
                 "graphene_contrast_threshold" => {
-                    settings.config.bacteria_exclusion.contrast_threshold = value.parse().unwrap();
+                    settings.config.bacteria_exclusion.contrast_threshold = parse(name, value)?;
+                }
+                "edge_detector" => {
+                    // The interface uses the default parameters of each method
+                    settings.config.bacteria_exclusion.edge_detector = EdgeDetector::method(value)
+                        .ok_or_else(|| format!("There is no edge detector named {value:?}"))?;
+                }
+                "threshold_selection" => {
+                    // The interface uses the default parameters of each method
+                    settings.config.bacteria_exclusion.threshold_selection =
+                        ThresholdSelection::method(value).ok_or_else(|| {
+                            format!("There is no threshold selection named {value:?}")
+                        })?;
+                }
+                "exclusion_mode" => {
+                    // The interface uses the default parameters of each method
+                    settings.config.bacteria_exclusion.exclusion_mode =
+                        ExclusionMode::method(value)
+                            .ok_or_else(|| format!("There is no exclusion mode named {value:?}"))?;
                 }
                 "graphene_minimum_edge_area" => {
-                    settings.config.bacteria_exclusion.minimum_edge_area = value.parse().unwrap();
+                    settings.config.bacteria_exclusion.minimum_edge_area = parse(name, value)?;
                 }
                 "graphene_edge_bacteria_exclusion_radius" => {
-                    settings.config.bacteria_exclusion.exclusion_radius = value.parse().unwrap();
+                    settings.config.bacteria_exclusion.exclusion_radius = parse(name, value)?;
                 }
                 // Graphene angles
                 "graphene_angles" => {
@@ -572,13 +827,43 @@ impl InteractiveConfiguration {
                     }
                 }
                 "graphene_angle_contrast_threshold" => {
-                    settings.config.graphene_angles.threshold = value.parse().unwrap();
+                    settings.config.graphene_angles.threshold = parse(name, value)?;
                 }
                 "min_graphene_size" => {
-                    settings.config.graphene_angles.min_graphene_size = value.parse().unwrap();
+                    settings.config.graphene_angles.min_graphene_size = parse(name, value)?;
                 }
                 "min_graphene_ratio" => {
-                    settings.config.graphene_angles.min_graphene_ratio = value.parse().unwrap();
+                    settings.config.graphene_angles.min_graphene_ratio = parse(name, value)?;
+                }
+                // Bacteria detection
+                "bacteria_detection" => {
+                    if value == "no" {
+                        settings.config.bacteria_detection.enabled = false;
+                    } else if value == "on" {
+                        settings.config.bacteria_detection.enabled = true;
+                    }
+                }
+                "bacteria_threshold" => {
+                    settings.config.bacteria_detection.threshold = parse(name, value)?;
+                }
+                "bacteria_polarity" => {
+                    settings.config.bacteria_detection.polarity = match value {
+                        "light" => Polarity::Light,
+                        "dark" => Polarity::Dark,
+                        _ => return Err(format!("There is no polarity named {value:?}").into()),
+                    };
+                }
+                "bacteria_min_length" => {
+                    settings.config.bacteria_detection.min_length = parse(name, value)?;
+                }
+                "bacteria_max_length" => {
+                    settings.config.bacteria_detection.max_length = parse(name, value)?;
+                }
+                "bacteria_min_width" => {
+                    settings.config.bacteria_detection.min_width = parse(name, value)?;
+                }
+                "bacteria_max_width" => {
+                    settings.config.bacteria_detection.max_width = parse(name, value)?;
                 }
                 // Export / import
                 "configuration_path" => settings.configuration_path = value.into(),
@@ -603,3 +888,14 @@ impl InteractiveConfiguration {
         Ok(settings)
     }
 }
+
+/// Parses the value of a query parameter, naming the parameter in the error
+fn parse<T>(name: &str, value: &str) -> Result<T, Box<dyn std::error::Error>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value {value:?} for {name} ({e})").into())
+}
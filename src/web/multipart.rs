@@ -0,0 +1,146 @@
+/// A part of a `multipart/form-data` body, such as a form field or an uploaded file
+#[derive(Debug)]
+pub struct Part<'a> {
+    /// The name of the form field
+    pub name: String,
+    /// The name of the uploaded file, if the part is a file
+    pub filename: Option<String>,
+    pub content: &'a [u8],
+}
+
+/// Gets the boundary separating the parts from the `Content-Type` header of a multipart body
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut parameters = content_type.split(';').map(str::trim);
+    if !parameters
+        .next()?
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    parameters
+        .find_map(|parameter| parameter.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Splits a `multipart/form-data` body into its parts, returns `None` if it is malformed
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Option<Vec<Part<'a>>> {
+    let delimiter = [b"--", boundary.as_bytes()].concat();
+    let mut parts = Vec::new();
+
+    // Anything before the first delimiter is ignored
+    let mut position = find(body, &delimiter, 0)? + delimiter.len();
+    loop {
+        // The last delimiter is followed by "--"
+        if body[position..].starts_with(b"--") {
+            return Some(parts);
+        }
+
+        let start = position + body[position..].strip_prefix(b"\r\n").map_or(0, |_| 2);
+        let end = find(body, &delimiter, start)?;
+
+        // The content is separated from the delimiter by a line break
+        let part = &body[start..end];
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        parts.push(parse_part(part)?);
+
+        position = end + delimiter.len();
+    }
+}
+
+/// Parses the headers and content of a single part
+fn parse_part(part: &[u8]) -> Option<Part<'_>> {
+    let separator = find(part, b"\r\n\r\n", 0)?;
+    let headers = std::str::from_utf8(&part[..separator]).ok()?;
+    let content = &part[separator + 4..];
+
+    let mut name = None;
+    let mut filename = None;
+    for header in headers.lines() {
+        let Some((field, value)) = header.split_once(':') else {
+            continue;
+        };
+
+        if !field.trim().eq_ignore_ascii_case("Content-Disposition") {
+            continue;
+        }
+
+        for parameter in value.split(';').map(str::trim) {
+            if let Some(value) = parameter.strip_prefix("name=") {
+                name = Some(value.trim_matches('"').to_string());
+            } else if let Some(value) = parameter.strip_prefix("filename=") {
+                filename = Some(value.trim_matches('"').to_string());
+            }
+        }
+    }
+
+    Some(Part {
+        name: name?,
+        filename,
+        content,
+    })
+}
+
+/// Finds the first position of the needle in the haystack at or after the start
+fn find(haystack: &[u8], needle: &[u8], start: usize) -> Option<usize> {
+    haystack
+        .get(start..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| start + position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{boundary, parse};
+
+    #[test]
+    fn reads_the_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"abc\""),
+            Some("abc")
+        );
+        assert_eq!(boundary("Multipart/Form-Data;boundary=abc"), Some("abc"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn splits_the_parts() {
+        let body = b"preamble\r\n--abc\r\n\
+            Content-Disposition: form-data; name=\"radius\"\r\n\r\n\
+            1.5\r\n--abc\r\n\
+            Content-Disposition: form-data; name=\"image\"; filename=\"a.tif\"\r\n\
+            Content-Type: image/tiff\r\n\r\n\
+            line\r\nbreak\r\n--abc--\r\n";
+
+        let parts = parse(body, "abc").unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "radius");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].content, b"1.5");
+        assert_eq!(parts[1].name, "image");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.tif"));
+
+        // Line breaks in the content are kept, only the one before the delimiter is removed
+        assert_eq!(parts[1].content, b"line\r\nbreak");
+    }
+
+    #[test]
+    fn rejects_a_missing_terminator() {
+        let body = b"--abc\r\nContent-Disposition: form-data; name=\"radius\"\r\n\r\n1.5\r\n";
+
+        assert!(parse(body, "abc").is_none());
+        assert!(parse(b"no delimiter", "abc").is_none());
+    }
+
+    #[test]
+    fn rejects_headers_without_crlf() {
+        let body = b"--abc\nContent-Disposition: form-data; name=\"radius\"\n\n1.5\n--abc--";
+
+        assert!(parse(body, "abc").is_none());
+    }
+}
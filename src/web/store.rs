@@ -0,0 +1,57 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Keeps the most recently used values in memory under random ids, such as the artifacts of
+/// the analyses in the web interface. The least recently used value is evicted when full
+#[derive(Debug)]
+pub struct Store<T> {
+    /// The values ordered from most to least recently used
+    recent: Mutex<VecDeque<(String, Arc<T>)>>,
+    capacity: usize,
+    /// Randomizes the ids, so they can't be guessed from each other
+    random: RandomState,
+    counter: AtomicU64,
+}
+
+impl<T> Store<T> {
+    pub fn new(capacity: usize) -> Self {
+        Store {
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            random: RandomState::new(),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Stores a new value and returns its id
+    pub fn insert(&self, value: T) -> String {
+        let id = format!(
+            "{:016x}",
+            self.random
+                .hash_one(self.counter.fetch_add(1, Ordering::Relaxed))
+        );
+
+        let mut recent = self.recent.lock().unwrap();
+        recent.truncate(self.capacity.saturating_sub(1));
+        recent.push_front((id.clone(), Arc::new(value)));
+
+        id
+    }
+
+    /// Gets a value by id, marking it as the most recently used
+    pub fn get(&self, id: &str) -> Option<Arc<T>> {
+        let mut recent = self.recent.lock().unwrap();
+        let index = recent.iter().position(|(value_id, _)| value_id == id)?;
+        let entry = recent.remove(index)?;
+        let value = Arc::clone(&entry.1);
+        recent.push_front(entry);
+
+        Some(value)
+    }
+}